@@ -1,35 +1,159 @@
-use clap::Parser;
+use clap::{
+    ArgMatches, Args, CommandFactory, ErrorKind, FromArgMatches, Parser, Subcommand, ValueEnum,
+    ValueSource,
+};
 use clap_verbosity_flag::Verbosity;
 
 /// Opinionated typescript project generator with a RedwoodJS core
 #[derive(Parser, Debug)]
 #[clap(version, about, long_about = None)]
 pub struct CliArgs {
-    /// Create default config file
-    #[clap(long, short, action)]
-    pub init: bool,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
 
-    /// Project name and path.
+    /// Project name and path, short for `lumberstack new <NAME>`
     #[clap(value_parser)]
     pub name: Option<String>,
 
+    /// Run tag(s) (comma separated)
+    #[clap(short, long, value_parser, value_delimiter = ',')]
+    pub only: Vec<String>,
+
+    #[clap(flatten)]
+    pub build: BuildArgs,
+
     /// Verbose logging (-v = warn, -vv = info)
     #[clap(flatten)]
     pub verbose: Verbosity,
 
-    /// Run tag(s) (comma separated)
-    #[clap(short, long)]
-    pub only: Option<Vec<String>>,
+    /// Load config from file
+    #[clap(short, long, value_parser, global = true)]
+    pub config: Option<String>,
+}
+
+// `lumberstack new` options that also work without a subcommand, by id and as written
+const NEW_ARGS: [(&str, &str); 5] = [
+    ("name", "<NAME>"),
+    ("only", "--only"),
+    ("disable-checks", "--disable-checks"),
+    ("markdown-report", "--markdown-report"),
+    ("output", "--output"),
+];
+
+impl CliArgs {
+    /// Parses the command line, rejecting `new` options given before a subcommand,
+    /// which would otherwise be ignored
+    pub fn parse_args() -> CliArgs {
+        let mut command = CliArgs::command();
+        let matches = command.get_matches_mut();
+        if let Some((subcommand, _)) = matches.subcommand() {
+            if let Some(arg) = Self::given(&matches) {
+                command
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        format!(
+                            "{} can't come before `{}`, pass it after the subcommand",
+                            arg, subcommand
+                        ),
+                    )
+                    .exit();
+            }
+        }
+        CliArgs::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+    }
+
+    fn given(matches: &ArgMatches) -> Option<&'static str> {
+        NEW_ARGS
+            .iter()
+            .find(|(id, _)| matches.value_source(id) == Some(ValueSource::CommandLine))
+            .map(|(_, arg)| *arg)
+    }
+}
 
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Generate a new app, running every step
+    New {
+        /// Project name and path. Overrides manifest value (if present)
+        #[clap(value_parser)]
+        name: Option<String>,
+
+        /// Run tag(s) (comma separated)
+        #[clap(short, long, value_parser, value_delimiter = ',')]
+        only: Vec<String>,
+
+        #[clap(flatten)]
+        build: BuildArgs,
+    },
+    /// Write the default manifest to ./lumberstack.json
+    Init,
+    /// Remove the app, its containers and volumes, and the downloaded templates
+    Clean {
+        /// Project name and path. Overrides manifest value (if present)
+        #[clap(value_parser)]
+        name: Option<String>,
+    },
+    /// Run only the steps with these tags
+    Run {
+        /// Step tags, space or comma separated
+        #[clap(value_parser, value_delimiter = ',', required = true)]
+        tags: Vec<String>,
+
+        /// Project name and path. Overrides manifest value (if present)
+        #[clap(short, long, value_parser)]
+        name: Option<String>,
+
+        #[clap(flatten)]
+        build: BuildArgs,
+    },
+    /// Show what a build would do without running anything
+    Plan {
+        /// Only plan the steps with these tags
+        #[clap(value_parser, value_delimiter = ',')]
+        tags: Vec<String>,
+
+        /// Project name and path. Overrides manifest value (if present)
+        #[clap(short, long, value_parser)]
+        name: Option<String>,
+    },
+    /// Check the manifest is valid
+    Validate,
+    /// Run every system check without stopping at the first problem
+    Doctor {
+        /// Project name and path, where write access and disk space are checked
+        #[clap(value_parser)]
+        name: Option<String>,
+
+        /// Print the report as json, for pasting into an issue
+        #[clap(long, action)]
+        json: bool,
+    },
+    /// List the manifest's steps
+    List,
+}
+
+/// Options for the commands that build
+#[derive(Args, Debug, Clone)]
+pub struct BuildArgs {
     /// Disable system checks
     #[clap(short, long, action)]
     pub disable_checks: bool,
 
-    /// Cleanup dev dirs
+    /// Also write a markdown build report
     #[clap(long, action)]
-    pub clean: bool,
+    pub markdown_report: bool,
 
-    /// Load config from file
-    #[clap(short, long, value_parser)]
-    pub config: Option<String>,
+    /// How progress is shown
+    #[clap(long, value_enum, default_value_t = Output::Spinner)]
+    pub output: Output,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Output {
+    /// Interactive spinner
+    Spinner,
+    /// Line oriented logs, for CI
+    Plain,
+    /// Newline delimited json events
+    Json,
 }
@@ -0,0 +1,108 @@
+use crate::report::REPORT_DIR;
+use log::warn;
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+// lines of a failed command's output shown with the error
+const TAIL_LINES: usize = 20;
+
+/// Everything a command printed, kept in `.lumberstack/logs/<tag>/<n>-<program>.log`
+/// whatever the verbosity
+pub struct CommandLog {
+    path: PathBuf,
+    file: Option<File>,
+    tail: VecDeque<String>,
+}
+
+impl CommandLog {
+    pub fn dir(tag: &str) -> PathBuf {
+        Path::new(REPORT_DIR)
+            .join("logs")
+            .join(Self::file_safe(tag))
+    }
+
+    /// Drops the logs of the step's last run so they don't mix with this one
+    pub fn clear(tag: &str) {
+        let dir = Self::dir(tag);
+        if dir.exists() {
+            if let Err(e) = fs::remove_dir_all(&dir) {
+                warn!("Could not clear old logs in {} - {}", dir.display(), e);
+            }
+        }
+    }
+
+    /// `n` is the command's position in its step, a log that can't be opened only warns
+    pub fn create(tag: &str, n: usize, program: &str) -> CommandLog {
+        let program = Path::new(program)
+            .file_name()
+            .map_or(program.to_string(), |p| p.to_string_lossy().to_string());
+        let dir = Self::dir(tag);
+        let path = dir.join(format!("{}-{}.log", n, Self::file_safe(&program)));
+
+        let file = fs::create_dir_all(&dir).and_then(|_| File::create(&path));
+        let file = match file {
+            Ok(file) => Some(file),
+            Err(e) => {
+                warn!("Could not create log {} - {}", path.display(), e);
+                None
+            }
+        };
+
+        CommandLog {
+            path,
+            file,
+            tail: VecDeque::with_capacity(TAIL_LINES),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Starts a new attempt, only its output counts towards the tail
+    pub fn attempt(&mut self, command: &str, cwd: &str, attempt: u32) {
+        self.tail.clear();
+        self.write(&format!("$ {} (in {}, attempt {})", command, cwd, attempt));
+    }
+
+    pub fn line(&mut self, line: &str) {
+        if self.tail.len() == TAIL_LINES {
+            self.tail.pop_front();
+        }
+        self.tail.push_back(line.to_string());
+        self.write(line);
+    }
+
+    pub fn finish(&mut self, outcome: String) {
+        self.write(&format!("# {}", outcome));
+    }
+
+    /// The last lines the latest attempt printed
+    pub fn tail(&self) -> Vec<String> {
+        self.tail.iter().cloned().collect()
+    }
+
+    fn write(&mut self, line: &str) {
+        if let Some(file) = &mut self.file {
+            if let Err(e) = writeln!(file, "{}", line) {
+                warn!("Could not write to {} - {}", self.path.display(), e);
+                self.file = None;
+            }
+        }
+    }
+
+    fn file_safe(name: &str) -> String {
+        name.chars()
+            .map(
+                |c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                    true => c,
+                    false => '_',
+                },
+            )
+            .collect()
+    }
+}
@@ -1,65 +1,406 @@
+use crate::command_log::CommandLog;
+use crate::error::{LumberstackError, Result};
 use crate::manifest::CommandItem;
-use indicatif::ProgressBar;
-use log::{debug, error};
-use std::process::{exit, Command, Stdio};
+use crate::reporter::{Event, Reporter};
+use crate::signals::{Signals, Terminal};
+use log::debug;
+use regex::Regex;
+use std::io::{self, BufRead, BufReader, IsTerminal, Read};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// how often a running command is checked for exit, timeout and signals
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// how long a stopped command gets to clean up before it is killed
+const GRACE_PERIOD: Duration = Duration::from_secs(5);
+// how long to keep reading output after a command is gone, something it started
+// in the background can hold its pipes open forever
+const DRAIN_PERIOD: Duration = Duration::from_secs(2);
+const DEFAULT_RETRY_DELAY: u64 = 1;
 
 pub struct Commands;
 
 impl Commands {
-    pub fn process(commands: Vec<CommandItem>, spinner: &ProgressBar) {
-        spinner.set_prefix("👟");
-        for command_step in commands.iter() {
+    /// Runs a step's commands, `tag` names the step's log dir
+    pub fn process(tag: &str, commands: Vec<CommandItem>, reporter: &dyn Reporter) -> Result<()> {
+        CommandLog::clear(tag);
+        for (n, command_step) in commands.iter().enumerate() {
+            Signals::check()?;
             let feedback = command_step.feedback.to_owned();
 
-            if let Some(feedback) = feedback {
-                spinner.set_message(feedback);
+            if let Some(message) = feedback {
+                reporter.report(&Event::Feedback { message });
             }
 
-            Self::exec_command(&command_step);
+            let program = command_step.command.split(" ").next().unwrap_or_default();
+            let mut log = CommandLog::create(tag, n + 1, program);
+            Self::exec_command(command_step, &mut log, reporter)?;
         }
+        Ok(())
     }
 
-    pub fn exec_command(command_step: &CommandItem) {
+    pub fn exec_command(
+        command_step: &CommandItem,
+        log: &mut CommandLog,
+        reporter: &dyn Reporter,
+    ) -> Result<()> {
         let command = &command_step.command;
         let context = &command_step.context.clone().unwrap_or(".".to_string());
+        let timeout = command_step.timeout.map(Duration::from_secs);
+        let retry_on = match &command_step.retry_on_output {
+            Some(pattern) => Some(Regex::new(pattern).map_err(|e| {
+                LumberstackError::manifest(command, format!("Invalid retry_on_output - {}", e))
+            })?),
+            None => None,
+        };
 
         debug!("👀 Running command: [{}]", &command);
+        // retry_on_output has to read the output, so it can't go straight to the terminal
+        let pass_through = reporter.passes_output_through() && retry_on.is_none();
 
         let cmd_vec: Vec<&str> = command.split(" ").collect();
         let (program, args) = cmd_vec.split_at(1);
 
-        Self::exec_raw(context, program[0], args, true);
+        let attempts = command_step.retries.unwrap_or(0) + 1;
+        let mut delay =
+            Duration::from_secs(command_step.retry_delay.unwrap_or(DEFAULT_RETRY_DELAY));
+        for attempt in 1..=attempts {
+            // without retry_on_output every failure is retried
+            let mut matched = retry_on.is_none();
+            log.attempt(command, context, attempt);
+            if pass_through {
+                log.line("# output went to the terminal, run without -vv to log it");
+            }
+            let result = Self::run(
+                context,
+                program[0],
+                args,
+                timeout,
+                pass_through,
+                reporter,
+                &mut |line| {
+                    log.line(line);
+                    if let Some(retry_on) = &retry_on {
+                        matched |= retry_on.is_match(line);
+                    }
+                },
+            );
+            log.finish(match &result {
+                Ok(()) => "ok".to_string(),
+                Err(e) => e.to_string(),
+            });
+
+            match result {
+                Err(e) if attempt < attempts && matched && !e.is_interrupted() => {
+                    reporter.report(&Event::CommandRetrying {
+                        argv: cmd_vec.iter().map(|a| a.to_string()).collect(),
+                        attempt,
+                        attempts,
+                        delay_ms: delay.as_millis() as u64,
+                        error: e.to_string(),
+                    });
+                    Self::sleep(delay)?;
+                    delay *= 2;
+                }
+                Err(e) if !e.is_interrupted() => {
+                    reporter.report(&Event::CommandFailed {
+                        argv: cmd_vec.iter().map(|a| a.to_string()).collect(),
+                        log: log.path().display().to_string(),
+                        tail: log.tail(),
+                    });
+                    return Err(e);
+                }
+                result => return result,
+            }
+        }
+        Ok(())
     }
 
-    pub fn exec_raw(context: &str, program: &str, args: &[&str], panic_fail: bool) {
-        let std_err = Self::should_inherit_stdio();
-        let std_out = Self::should_inherit_stdio();
+    pub fn exec_raw(
+        context: &str,
+        program: &str,
+        args: &[&str],
+        panic_fail: bool,
+        timeout: Option<Duration>,
+        reporter: &dyn Reporter,
+    ) -> Result<()> {
+        let pass_through = reporter.passes_output_through();
+        match Self::run(
+            context,
+            program,
+            args,
+            timeout,
+            pass_through,
+            reporter,
+            &mut |_| {},
+        ) {
+            Err(e) if !panic_fail && !e.is_interrupted() => {
+                debug!("ignoring failure: {}", e);
+                Ok(())
+            }
+            result => result,
+        }
+    }
 
-        let child = Command::new(program)
-            .args(args)
+    /// Runs a command once, every line of its output is passed to `on_line` unless
+    /// `pass_through` leaves it on the terminal
+    fn run(
+        context: &str,
+        program: &str,
+        args: &[&str],
+        timeout: Option<Duration>,
+        pass_through: bool,
+        reporter: &dyn Reporter,
+        on_line: &mut dyn FnMut(&str),
+    ) -> Result<()> {
+        let argv: Vec<String> = [&[program], args]
+            .concat()
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        let command = argv.join(" ");
+
+        let mut cmd = Command::new(program);
+        // output is piped so every reporter sees it and they decide what to show, unless
+        // it's shown as is, where commands have to see a terminal to keep colours and prompts
+        let output = || match pass_through {
+            true => Stdio::inherit(),
+            false => Stdio::piped(),
+        };
+        cmd.args(args)
             .stdin(Stdio::inherit())
-            .stderr(std_err)
-            .stdout(std_out)
-            .current_dir(context)
-            .spawn();
-
-        match child {
-            Ok(c) => {
-                c.wait_with_output().expect("ERROR RUNNING COMMAND");
+            .stderr(output())
+            .stdout(output())
+            .current_dir(context);
+
+        // every command gets its own process group so it can be stopped along with everything
+        // it started. On a terminal that group is made the foreground one so prompts and Ctrl-C
+        // still reach it.
+        let foreground = io::stdin().is_terminal();
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            if foreground {
+                // SAFETY: setpgid, getpid and Terminal::hand_to are async-signal-safe
+                unsafe {
+                    cmd.pre_exec(|| {
+                        libc::setpgid(0, 0);
+                        Terminal::hand_to(libc::getpid());
+                        Ok(())
+                    });
+                }
+            } else {
+                cmd.process_group(0);
+            }
+        }
+
+        let started = Instant::now();
+        match cmd.spawn() {
+            Ok(mut c) => {
+                reporter.report(&Event::CommandStarted {
+                    argv: argv.clone(),
+                    cwd: context.to_string(),
+                });
+
+                #[cfg(unix)]
+                if foreground {
+                    // the child does this too, whichever runs first wins the race with its reads
+                    Terminal::hand_to(c.id() as libc::pid_t);
+                }
+                let waited = match pass_through {
+                    true => {
+                        let mut waited = None;
+                        reporter.suspend(&mut || {
+                            waited =
+                                Some(Self::wait(&mut c, foreground, timeout, reporter, on_line))
+                        });
+                        waited.expect("suspend runs its closure")
+                    }
+                    false => Self::wait(&mut c, foreground, timeout, reporter, on_line),
+                };
+                #[cfg(unix)]
+                if foreground {
+                    Terminal::take_back();
+                }
+                let code = waited.as_ref().ok().and_then(|status| status.code());
+                reporter.report(&Event::CommandExited {
+                    argv,
+                    cwd: context.to_string(),
+                    code,
+                    duration_ms: started.elapsed().as_millis() as u64,
+                });
+
+                match waited {
+                    Ok(status) if status.success() => Ok(()),
+                    Ok(status) => Err(LumberstackError::command(
+                        &command,
+                        format!("exited with {}", status),
+                    )),
+                    Err(Stopped::Timeout(timeout)) => Err(LumberstackError::command(
+                        &command,
+                        format!("timed out after {}s", timeout.as_secs()),
+                    )),
+                    Err(Stopped::Signal(signal)) => Err(LumberstackError::Interrupted(signal)),
+                    Err(Stopped::Io(e)) => Err(LumberstackError::command(&command, e)),
+                }
+            }
+            Err(e) => Err(LumberstackError::command(
+                &command,
+                format!("could not start {} in {} - {}", program, context, e),
+            )),
+        }
+    }
+
+    /// Waits between retries, giving up early on a signal
+    fn sleep(delay: Duration) -> Result<()> {
+        let deadline = Instant::now() + delay;
+        while Instant::now() < deadline {
+            Signals::check()?;
+            thread::sleep(POLL_INTERVAL.min(deadline - Instant::now()));
+        }
+        Signals::check()
+    }
+
+    /// Forwards output until the child exits, stopping it on timeout or a signal
+    fn wait(
+        child: &mut Child,
+        foreground: bool,
+        timeout: Option<Duration>,
+        reporter: &dyn Reporter,
+        on_line: &mut dyn FnMut(&str),
+    ) -> std::result::Result<ExitStatus, Stopped> {
+        let (tx, rx) = mpsc::channel();
+        let mut readers = vec![];
+        if let Some(stdout) = child.stdout.take() {
+            readers.push(Self::forward_lines(stdout, tx.clone()));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            readers.push(Self::forward_lines(stderr, tx.clone()));
+        }
+        drop(tx);
+
+        let deadline = timeout.map(|t| (Instant::now() + t, t));
+        let result = loop {
+            match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(line) => {
+                    on_line(&line);
+                    reporter.report(&Event::CommandOutput { line });
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => thread::sleep(POLL_INTERVAL),
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+
+            match child.try_wait() {
+                // Ctrl-C only reaches a command that has the terminal, not us
+                Ok(Some(status)) if foreground && Self::interrupted(&status) => {
+                    break Err(Stopped::Signal(signal_hook::consts::SIGINT))
+                }
+                Ok(Some(status)) => break Ok(status),
+                Ok(None) => {}
+                Err(e) => break Err(Stopped::Io(e)),
+            }
+
+            if let Some(signal) = Signals::received() {
+                debug!("forwarding {} to {}", Signals::name(signal), child.id());
+                Self::stop(child, signal);
+                break Err(Stopped::Signal(signal));
             }
-            Err(e) => {
-                if panic_fail {
-                    error!("Error running command {} - {}", program, e);
-                    exit(exitcode::IOERR);
+            if let Some((deadline, timeout)) = deadline {
+                if Instant::now() >= deadline {
+                    debug!("{} timed out, stopping it", child.id());
+                    Self::stop(child, signal_hook::consts::SIGTERM);
+                    break Err(Stopped::Timeout(timeout));
+                }
+            }
+        };
+
+        // the readers finish once nothing holds the pipes, or are left behind after a while
+        let drain_until = Instant::now() + DRAIN_PERIOD;
+        loop {
+            let remaining = drain_until.saturating_duration_since(Instant::now());
+            match rx.recv_timeout(remaining) {
+                Ok(line) => {
+                    on_line(&line);
+                    reporter.report(&Event::CommandOutput { line });
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {
+                    debug!("{}'s output is still open, not waiting for it", child.id());
+                    break;
                 }
             }
         }
+        for reader in readers.into_iter().filter(|r| r.is_finished()) {
+            let _ = reader.join();
+        }
+        result
+    }
+
+    #[cfg(unix)]
+    fn interrupted(status: &ExitStatus) -> bool {
+        use std::os::unix::process::ExitStatusExt;
+        status.signal() == Some(libc::SIGINT) || status.code() == Some(128 + libc::SIGINT)
     }
 
-    fn should_inherit_stdio() -> Stdio {
-        if log::log_enabled!(log::Level::Info) {
-            return Stdio::inherit();
+    #[cfg(not(unix))]
+    fn interrupted(_status: &ExitStatus) -> bool {
+        false
+    }
+
+    /// Sends `signal` to the child's process group, then kills the group after the grace period
+    #[cfg(unix)]
+    fn stop(child: &mut Child, signal: i32) {
+        let pid = child.id() as libc::pid_t;
+        let target = -pid;
+
+        // SAFETY: plain kill(2), the pid is our own child's
+        unsafe { libc::kill(target, signal) };
+        let deadline = Instant::now() + GRACE_PERIOD;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = child.try_wait() {
+                return;
+            }
+            thread::sleep(POLL_INTERVAL);
         }
-        return Stdio::piped();
+
+        debug!("{} ignored {}, killing it", pid, Signals::name(signal));
+        unsafe { libc::kill(target, libc::SIGKILL) };
+        let _ = child.wait();
+    }
+
+    #[cfg(not(unix))]
+    fn stop(child: &mut Child, _signal: i32) {
+        let _ = child.kill();
+        let _ = child.wait();
     }
+
+    fn forward_lines(pipe: impl Read + Send + 'static, tx: Sender<String>) -> JoinHandle<()> {
+        thread::spawn(move || {
+            let mut reader = BufReader::new(pipe);
+            let mut buf = vec![];
+            while let Ok(n) = reader.read_until(b'\n', &mut buf) {
+                if n == 0 {
+                    break;
+                }
+                let line = String::from_utf8_lossy(&buf);
+                if tx
+                    .send(line.trim_end_matches(['\r', '\n']).to_string())
+                    .is_err()
+                {
+                    break;
+                }
+                buf.clear();
+            }
+        })
+    }
+}
+
+/// Why a command didn't get to exit on its own
+enum Stopped {
+    Timeout(Duration),
+    Signal(i32),
+    Io(io::Error),
 }
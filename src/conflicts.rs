@@ -0,0 +1,148 @@
+use crate::manifest::ConflictPolicy;
+use crate::reporter::Reporter;
+use serde::Serialize;
+use similar::TextDiff;
+use std::{
+    error::Error,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileOutcome {
+    Created,
+    Overwritten,
+    Unchanged,
+    Skipped,
+    BackedUp,
+    Merged,
+}
+
+impl FileOutcome {
+    pub fn label(&self) -> &'static str {
+        match self {
+            FileOutcome::Created => "created",
+            FileOutcome::Overwritten => "overwritten",
+            FileOutcome::Unchanged => "unchanged",
+            FileOutcome::Skipped => "skipped",
+            FileOutcome::BackedUp => "backed up",
+            FileOutcome::Merged => "merged",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TemplateSummary {
+    pub files: Vec<(PathBuf, FileOutcome)>,
+}
+
+impl TemplateSummary {
+    pub fn record(&mut self, path: &Path, outcome: FileOutcome) {
+        self.files.push((path.to_path_buf(), outcome));
+    }
+
+    pub fn count(&self, outcome: FileOutcome) -> usize {
+        self.files.iter().filter(|(_, o)| *o == outcome).count()
+    }
+
+    /// Counts per outcome followed by every file that wasn't left unchanged
+    pub fn lines(&self) -> Vec<String> {
+        if self.files.is_empty() {
+            return vec![];
+        }
+
+        let outcomes = [
+            FileOutcome::Created,
+            FileOutcome::Overwritten,
+            FileOutcome::BackedUp,
+            FileOutcome::Merged,
+            FileOutcome::Skipped,
+            FileOutcome::Unchanged,
+        ];
+        let counts: Vec<String> = outcomes
+            .iter()
+            .map(|o| (o, self.count(*o)))
+            .filter(|(_, n)| *n > 0)
+            .map(|(o, n)| format!("{} {}", n, o.label()))
+            .collect();
+
+        let mut lines = vec![format!("📄 Template files: {}", counts.join(", "))];
+        for (path, outcome) in self.files.iter() {
+            if *outcome != FileOutcome::Unchanged {
+                lines.push(format!("   {:<12}{}", outcome.label(), path.display()));
+            }
+        }
+        lines
+    }
+}
+
+pub struct Conflicts;
+
+impl Conflicts {
+    /// Shows a diff of an existing file against the template output and asks what to do
+    pub fn prompt(
+        reporter: &dyn Reporter,
+        to: &Path,
+        existing: &[u8],
+        incoming: &[u8],
+        is_text: bool,
+    ) -> Result<ConflictPolicy, Box<dyn Error>> {
+        if !io::stdin().is_terminal() {
+            return Err(format!(
+                "{} already exists and there is no terminal to prompt on",
+                to.display()
+            )
+            .into());
+        }
+
+        let mut answer = Ok(ConflictPolicy::Error);
+        reporter.suspend(&mut || {
+            println!("⚠️  {} already exists", to.display());
+            Self::print_diff(to, existing, incoming, is_text);
+            answer = Self::ask();
+        });
+
+        Ok(answer?)
+    }
+
+    fn ask() -> io::Result<ConflictPolicy> {
+        loop {
+            print!("[o]verwrite, [s]kip, [b]ackup, [m]erge, [a]bort? ");
+            io::stdout().flush()?;
+
+            let mut line = String::new();
+            io::stdin().read_line(&mut line)?;
+
+            match line.trim() {
+                "o" => return Ok(ConflictPolicy::Overwrite),
+                "s" => return Ok(ConflictPolicy::Skip),
+                "b" => return Ok(ConflictPolicy::Backup),
+                "m" => return Ok(ConflictPolicy::Merge),
+                "a" => return Ok(ConflictPolicy::Error),
+                _ => continue,
+            }
+        }
+    }
+
+    fn print_diff(to: &Path, existing: &[u8], incoming: &[u8], is_text: bool) {
+        let (existing, incoming) =
+            match (std::str::from_utf8(existing), std::str::from_utf8(incoming)) {
+                (Ok(existing), Ok(incoming)) if is_text => (existing, incoming),
+                _ => {
+                    println!("Binary files differ");
+                    return;
+                }
+            };
+
+        let name = to.display().to_string();
+        let diff = TextDiff::from_lines(existing, incoming);
+        print!(
+            "{}",
+            diff.unified_diff().header(
+                &format!("{} (existing)", name),
+                &format!("{} (template)", name)
+            )
+        );
+    }
+}
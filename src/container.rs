@@ -0,0 +1,52 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::process::{Command, Stdio};
+
+/// The CLI containers and compose services go through
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerRuntime {
+    #[default]
+    Docker,
+    Podman,
+    Nerdctl,
+}
+
+impl ContainerRuntime {
+    const ALL: [ContainerRuntime; 3] = [
+        ContainerRuntime::Docker,
+        ContainerRuntime::Podman,
+        ContainerRuntime::Nerdctl,
+    ];
+
+    pub fn cli(&self) -> &'static str {
+        match self {
+            ContainerRuntime::Docker => "docker",
+            ContainerRuntime::Podman => "podman",
+            ContainerRuntime::Nerdctl => "nerdctl",
+        }
+    }
+
+    /// All three take `<cli> compose ...`, podman through podman-compose or docker-compose
+    pub fn compose_args<'a>(&self, compose_file: &'a str, args: &[&'a str]) -> Vec<&'a str> {
+        [&["compose", "-f", compose_file], args].concat()
+    }
+
+    /// The configured runtime, or the first one installed, falling back to docker
+    pub fn resolve(configured: Option<ContainerRuntime>) -> ContainerRuntime {
+        configured.or_else(Self::detect).unwrap_or_default()
+    }
+
+    fn detect() -> Option<ContainerRuntime> {
+        let found = Self::ALL.into_iter().find(|runtime| {
+            Command::new(runtime.cli())
+                .arg("--version")
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success())
+        });
+        debug!("detected container runtime: {:?}", found);
+        found
+    }
+}
@@ -2,13 +2,27 @@ use log::info;
 
 pub fn generate_default_config() -> &'static str {
     info!("⚙️ Using default configuration");
-    return r#"{
+    r#"{
   "app_name": "myapp",
   "keep_templates": false,
+  "requirements": {
+    "node": ">=18 <21",
+    "yarn": ">=1.22",
+    "docker": ">=20"
+  },
+  "checks": [{ "name": "disk space", "free_disk_mb": 1024 }],
+  "services": [
+    {
+      "name": "db",
+      "compose_file": "docker-compose.yml",
+      "ports": [5432]
+    }
+  ],
   "builder": [
     {
       "tag": "download",
       "feedback": "fetching templates",
+      "requires": ["curl", "tar"],
       "commands": [
         {
           "command": "curl -L https://github.com/codingzeal/lumberstack-templates/archive/main.tar.gz -o /tmp/lumberstack-templates.tar.gz"
@@ -29,13 +43,14 @@ pub fn generate_default_config() -> &'static str {
     {
       "tag": "redwood",
       "feedback": "Creating Redwood App",
+      "requires": ["node", "{{pm}}"],
       "commands": [
         {
-          "command": "yarn create redwood-app {{app_name}} --typescript --overwrite",
+          "command": "{{pm}} create redwood-app {{app_name}} {{pm_args}} --typescript --overwrite",
           "context": "."
         },
         {
-          "command": "yarn rw generate page home / --force",
+          "command": "{{pm_exec}} rw generate page home / --force",
           "context": "{{app_name}}"
         }
       ]
@@ -50,22 +65,28 @@ pub fn generate_default_config() -> &'static str {
         }
       ]
     },
+    {
+      "tag": "database",
+      "feedback": "Starting docker postgres",
+      "services": ["db"]
+    },
     {
       "tag": "prisma",
       "feedback": "Setting up prisma",
+      "requires": ["node", "{{pm}}", "{{container_cli}}"],
+      "services": ["db"],
+      "wait_for": [
+        {
+          "feedback": "Waiting for postgres",
+          "command": "{{container_cli}} compose -f docker-compose.yml exec -T db pg_isready",
+          "context": "{{app_name}}",
+          "timeout": 60
+        }
+      ],
       "commands": [
-        {
-          "command": "docker compose -f docker-compose.yml stop db",
-          "context": "{{app_name}}"
-        },
-        {
-          "feedback": "Starting docker postgres",
-          "command": "docker compose -f docker-compose.yml up db -d",
-          "context": "{{app_name}}"
-        },
         {
           "feedback": "Creating migration",
-          "command": "yarn rw prisma migrate dev --name init",
+          "command": "{{pm_exec}} rw prisma migrate dev --name init",
           "context": "{{app_name}}"
         }
       ]
@@ -73,6 +94,7 @@ pub fn generate_default_config() -> &'static str {
     {
       "tag": "auth",
       "feedback": "Setting up redwood auth",
+      "requires": ["node", "{{pm}}"],
       "templates": [
         {
           "source": "templates/api/src/directives/requireAuth/requireAuth.test.ts",
@@ -81,11 +103,11 @@ pub fn generate_default_config() -> &'static str {
       ],
       "commands": [
         {
-          "command": "yarn rw setup auth dbAuth --force",
+          "command": "{{pm_exec}} rw setup auth dbAuth --force",
           "context": "{{app_name}}"
         },
         {
-          "command": "yarn rw generate dbAuth --force",
+          "command": "{{pm_exec}} rw generate dbAuth --force",
           "context": "{{app_name}}"
         }
       ]
@@ -93,9 +115,10 @@ pub fn generate_default_config() -> &'static str {
     {
       "tag": "tailwind",
       "feedback": "Setting up tailwind",
+      "requires": ["node", "{{pm}}"],
       "commands": [
         {
-          "command": "yarn rw setup ui tailwind",
+          "command": "{{pm_exec}} rw setup ui tailwind",
           "context": "{{app_name}}"
         }
       ]
@@ -103,9 +126,10 @@ pub fn generate_default_config() -> &'static str {
     {
       "tag": "playwright",
       "feedback": "Setting up playwright",
+      "requires": ["node", "{{pm}}"],
       "commands": [
         {
-          "command": "yarn create playwright --quiet --lang=ts",
+          "command": "{{pm}} create playwright {{pm_args}} --quiet --lang=ts",
           "context": "{{app_name}}/web/"
         }
       ]
@@ -113,9 +137,10 @@ pub fn generate_default_config() -> &'static str {
     {
       "tag": "heroku",
       "feedback": "Setting up heroku",
+      "requires": ["node", "{{pm}}"],
       "commands": [
         {
-          "command": "yarn add pm2",
+          "command": "{{pm_add}} pm2",
           "context": "{{app_name}}"
         },
         {
@@ -125,5 +150,5 @@ pub fn generate_default_config() -> &'static str {
     }
   ]
 }
-"#;
+"#
 }
@@ -0,0 +1,343 @@
+use crate::default_config::generate_default_config;
+use crate::error::LumberstackError;
+use crate::manifest::{BuildItem, Manifest};
+use crate::requirements::Requirements;
+use crate::sys_checks::System;
+use crate::DEFAULT_APP_NAME;
+use regex::Regex;
+use serde::Serialize;
+use std::{env, net::TcpListener, path::Path, process::Command};
+
+// proxies are shown with any credentials in them taken out
+const PROXY_VARS: [&str; 8] = [
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "ALL_PROXY",
+    "NO_PROXY",
+    "http_proxy",
+    "https_proxy",
+    "all_proxy",
+    "no_proxy",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FindingKind {
+    Os,
+    Manifest,
+    Tool,
+    ContainerRuntime,
+    Port,
+    Disk,
+    Proxy,
+    WriteAccess,
+    Check,
+}
+
+impl FindingKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            FindingKind::Os => "os",
+            FindingKind::Manifest => "manifest",
+            FindingKind::Tool => "tool",
+            FindingKind::ContainerRuntime => "container runtime",
+            FindingKind::Port => "port",
+            FindingKind::Disk => "disk",
+            FindingKind::Proxy => "proxy",
+            FindingKind::WriteAccess => "write access",
+            FindingKind::Check => "check",
+        }
+    }
+}
+
+/// One thing `doctor` looked at
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub kind: FindingKind,
+    pub name: String,
+    pub ok: bool,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+impl Finding {
+    fn new(kind: FindingKind, name: impl ToString, ok: bool, detail: impl ToString) -> Finding {
+        Finding {
+            kind,
+            name: name.to_string(),
+            ok,
+            detail: detail.to_string(),
+            path: None,
+            version: None,
+        }
+    }
+
+    /// A failed check, without the `System check failed` prefix every line would have
+    fn failed(kind: FindingKind, name: impl ToString, e: LumberstackError) -> Finding {
+        let detail = match e {
+            LumberstackError::SystemCheck(message) => message,
+            e => e.to_string(),
+        };
+        Finding::new(kind, name, false, detail)
+    }
+}
+
+/// Everything `lumberstack doctor` found, meant to be pasted into an issue
+#[derive(Debug, Serialize)]
+pub struct Diagnosis {
+    pub lumberstack_version: String,
+    pub os: String,
+    pub arch: String,
+    pub findings: Vec<Finding>,
+}
+
+impl Diagnosis {
+    pub fn ok(&self) -> bool {
+        self.findings.iter().all(|f| f.ok)
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "🩺 lumberstack {} on {} {}",
+            self.lumberstack_version, self.os, self.arch
+        )];
+        for finding in self.findings.iter() {
+            let flag = if finding.ok { "✅" } else { "❌" };
+            lines.push(format!(
+                "{} {:<19}{:<16}{}",
+                flag,
+                finding.kind.label(),
+                finding.name,
+                finding.detail
+            ));
+        }
+        let failed = self.findings.iter().filter(|f| !f.ok).count();
+        lines.push(match failed {
+            0 => "Everything looks good".to_string(),
+            n => format!("{} problem(s) found", n),
+        });
+        lines
+    }
+}
+
+pub struct Doctor;
+
+impl Doctor {
+    /// Runs every check, carrying on past failures, for the manifest at `config`
+    pub fn diagnose(config: Option<&str>, name: Option<&str>) -> Diagnosis {
+        let mut findings = vec![Self::os()];
+
+        let manifest = match Manifest::load(config, name) {
+            Ok(manifest) => {
+                findings.push(Finding::new(
+                    FindingKind::Manifest,
+                    &manifest.source,
+                    true,
+                    format!("app {}", manifest.app_name),
+                ));
+                Some(manifest)
+            }
+            Err(e) => {
+                findings.push(Finding::failed(
+                    FindingKind::Manifest,
+                    config.unwrap_or("manifest"),
+                    e,
+                ));
+                // carry on with what the default manifest needs
+                match Manifest::parse("default", generate_default_config(), name) {
+                    Ok(manifest) => Some(manifest),
+                    Err(e) => {
+                        findings.push(Finding::failed(FindingKind::Manifest, "default", e));
+                        None
+                    }
+                }
+            }
+        };
+
+        // without any manifest there's nothing to say which tools, ports and checks matter
+        if let Some(manifest) = &manifest {
+            findings.extend(Self::tools(manifest));
+            findings.push(Self::container_runtime(manifest));
+            findings.extend(Self::ports(manifest));
+            findings.extend(Self::disk(manifest));
+        }
+        findings.extend(Self::proxies());
+        let app_name = manifest
+            .as_ref()
+            .map(|m| m.app_name.as_str())
+            .or(name)
+            .unwrap_or(DEFAULT_APP_NAME);
+        findings.push(Self::write_access(app_name));
+
+        Diagnosis {
+            lumberstack_version: env!("CARGO_PKG_VERSION").to_string(),
+            os: env::consts::OS.to_string(),
+            arch: env::consts::ARCH.to_string(),
+            findings,
+        }
+    }
+
+    fn os() -> Finding {
+        let release = Command::new("uname")
+            .arg("-sr")
+            .output()
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+            .unwrap_or_default();
+        match System::os_ok() {
+            Ok(()) => Finding::new(FindingKind::Os, env::consts::OS, true, release),
+            Err(e) => Finding::failed(FindingKind::Os, env::consts::OS, e),
+        }
+    }
+
+    /// Tools every step requires, plus node, the package manager and the container runtime
+    fn tools(manifest: &Manifest) -> Vec<Finding> {
+        let items: Vec<&BuildItem> = manifest.json.builder.iter().collect();
+        let mut tools = vec![
+            "node".to_string(),
+            manifest.package_manager.cli().to_string(),
+            manifest.container_runtime.cli().to_string(),
+        ];
+        for tool in System::required_tools(manifest, &items) {
+            if !tools.contains(&tool) {
+                tools.push(tool);
+            }
+        }
+
+        let requirements = manifest.json.requirements.clone().unwrap_or_default();
+        tools
+            .iter()
+            .map(|tool| {
+                let path = Self::which(tool);
+                let version = Requirements::installed_version(tool);
+                let required = requirements.get(tool);
+                let in_range = match (&version, required) {
+                    (_, None) => true,
+                    (Some(version), Some(required)) => {
+                        Requirements::parse(required).is_ok_and(|range| range.matches(version))
+                    }
+                    (None, Some(_)) => false,
+                };
+
+                let version = version.map(|v| v.to_string());
+                let detail = match (&path, required) {
+                    (None, _) => "not found".to_string(),
+                    (Some(path), required) => format!(
+                        "{} {}{}",
+                        version.as_deref().unwrap_or("?"),
+                        path,
+                        required
+                            .map(|r| format!(" (needs {})", r))
+                            .unwrap_or_default()
+                    ),
+                };
+                let mut finding =
+                    Finding::new(FindingKind::Tool, tool, path.is_some() && in_range, detail);
+                finding.path = path;
+                finding.version = version;
+                finding
+            })
+            .collect()
+    }
+
+    fn container_runtime(manifest: &Manifest) -> Finding {
+        let cli = manifest.container_runtime.cli();
+        match System::check_container_runtime(manifest.container_runtime) {
+            Ok(()) => Finding::new(FindingKind::ContainerRuntime, cli, true, "running"),
+            Err(e) => Finding::failed(FindingKind::ContainerRuntime, cli, e),
+        }
+    }
+
+    fn ports(manifest: &Manifest) -> Vec<Finding> {
+        let services = manifest.json.services.iter().flatten();
+        services
+            .flat_map(|service| {
+                service.ports.iter().flatten().map(|port| {
+                    let free = TcpListener::bind(("0.0.0.0", *port)).is_ok();
+                    let detail = match free {
+                        true => format!("free for {}", service.name),
+                        false => format!("in use, {} can't start", service.name),
+                    };
+                    Finding::new(FindingKind::Port, port, free, detail)
+                })
+            })
+            .collect()
+    }
+
+    /// Free space where the app goes, and every custom check from the manifest
+    fn disk(manifest: &Manifest) -> Vec<Finding> {
+        let mut findings = vec![];
+        match System::free_disk_mb(Path::new(&manifest.app_name)) {
+            Ok(free) => findings.push(Finding::new(
+                FindingKind::Disk,
+                &manifest.app_name,
+                true,
+                format!("{}MB free", free),
+            )),
+            Err(e) => findings.push(Finding::new(
+                FindingKind::Disk,
+                &manifest.app_name,
+                false,
+                e,
+            )),
+        }
+
+        let step_checks = manifest
+            .json
+            .builder
+            .iter()
+            .flat_map(|item| item.checks.iter().flatten());
+        for check in manifest.json.checks.iter().flatten().chain(step_checks) {
+            findings.push(match System::check_custom(check) {
+                Ok(()) => Finding::new(FindingKind::Check, check.label(), true, "passed"),
+                Err(e) => Finding::failed(FindingKind::Check, check.label(), e),
+            });
+        }
+        findings
+    }
+
+    fn proxies() -> Vec<Finding> {
+        let credentials = Regex::new(r"//[^/@]+@").unwrap();
+        PROXY_VARS
+            .iter()
+            .filter_map(|var| env::var(var).ok().map(|value| (var, value)))
+            .map(|(var, value)| {
+                let value = credentials.replace(&value, "//[redacted]@");
+                Finding::new(FindingKind::Proxy, var, true, value)
+            })
+            .collect()
+    }
+
+    /// Whether the app dir, or the closest parent that exists, can be written to
+    fn write_access(app_name: &str) -> Finding {
+        let app_dir = Path::new(app_name);
+        let existing = app_dir
+            .ancestors()
+            .find(|p| p.is_dir())
+            .unwrap_or(Path::new("."));
+        match tempfile::tempfile_in(existing) {
+            Ok(_) => Finding::new(
+                FindingKind::WriteAccess,
+                app_name,
+                true,
+                format!("{} is writable", existing.display()),
+            ),
+            Err(e) => Finding::new(
+                FindingKind::WriteAccess,
+                app_name,
+                false,
+                format!("{} - {}", existing.display(), e),
+            ),
+        }
+    }
+
+    fn which(tool: &str) -> Option<String> {
+        let paths = env::var_os("PATH")?;
+        env::split_paths(&paths)
+            .map(|dir| dir.join(tool))
+            .find(|path| path.is_file())
+            .map(|path| path.display().to_string())
+    }
+}
@@ -0,0 +1,132 @@
+use std::io;
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, LumberstackError>;
+
+#[derive(Debug, Error)]
+pub enum LumberstackError {
+    #[error("Manifest error in {path} - {message}")]
+    Manifest { path: String, message: String },
+
+    #[error("Command [{command}] failed - {message}")]
+    Command { command: String, message: String },
+
+    #[error("Template error for {path} - {message}")]
+    Template { path: String, message: String },
+
+    #[error("Patch error for {file} - {message}")]
+    Patch { file: String, message: String },
+
+    #[error("Gave up waiting for {probe} - {message}")]
+    Wait { probe: String, message: String },
+
+    #[error("Service {name} could not be started - {message}")]
+    Service { name: String, message: String },
+
+    #[error("System check failed - {0}")]
+    SystemCheck(String),
+
+    #[error("{tool} {found} found but {required} required")]
+    Requirement {
+        tool: String,
+        found: String,
+        required: String,
+    },
+
+    #[error("Interrupted by {}", crate::signals::Signals::name(*.0))]
+    Interrupted(i32),
+
+    #[error("{context} - {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl LumberstackError {
+    pub fn manifest(path: &str, message: impl ToString) -> LumberstackError {
+        LumberstackError::Manifest {
+            path: path.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn command(command: &str, message: impl ToString) -> LumberstackError {
+        LumberstackError::Command {
+            command: command.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn template(path: &str, message: impl ToString) -> LumberstackError {
+        LumberstackError::Template {
+            path: path.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn io(context: impl ToString, source: io::Error) -> LumberstackError {
+        LumberstackError::Io {
+            context: context.to_string(),
+            source,
+        }
+    }
+
+    /// Interrupted builds stop right away, nothing retries or ignores them
+    pub fn is_interrupted(&self) -> bool {
+        matches!(self, LumberstackError::Interrupted(_))
+    }
+
+    pub fn exit_code(&self) -> exitcode::ExitCode {
+        match self {
+            LumberstackError::Manifest { .. } => exitcode::CONFIG,
+            LumberstackError::Command { .. } => exitcode::SOFTWARE,
+            LumberstackError::Template { .. } => exitcode::OSFILE,
+            LumberstackError::Patch { .. } => exitcode::DATAERR,
+            LumberstackError::Wait { .. } => exitcode::TEMPFAIL,
+            LumberstackError::Service { .. }
+            | LumberstackError::SystemCheck(_)
+            | LumberstackError::Requirement { .. } => exitcode::UNAVAILABLE,
+            LumberstackError::Io { .. } => exitcode::IOERR,
+            // shell convention, 130 for Ctrl-C
+            LumberstackError::Interrupted(signal) => 128 + signal,
+        }
+    }
+
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            LumberstackError::Manifest { .. } => Some(
+                "Check the manifest is valid json, or run `lumberstack init` for a fresh one",
+            ),
+            LumberstackError::Command { .. } => {
+                Some("The command's full output is under .lumberstack/logs")
+            }
+            LumberstackError::Template { .. } => {
+                Some("Check the template source exists and the destination is writable")
+            }
+            LumberstackError::Patch { .. } => {
+                Some("Check the patch pattern still matches the generated file")
+            }
+            LumberstackError::Wait { .. } => {
+                Some("Check the service actually started, or raise the wait_for timeout")
+            }
+            LumberstackError::Service { .. } => {
+                Some("Stop whatever holds the service's ports, or change them in the compose file")
+            }
+            LumberstackError::SystemCheck(_) => {
+                Some("Install the missing requirement or pass --disable-checks to skip")
+            }
+            LumberstackError::Requirement { tool, .. } => Some(match tool.as_str() {
+                "node" => "Switch node versions with nvm or fnm (`nvm install 20`), or get one from nodejs.org",
+                "yarn" | "pnpm" => "Run `corepack enable` to get the package manager version the project pins",
+                "npm" => "npm comes with node, switching node versions usually fixes it",
+                "docker" | "podman" | "nerdctl" => {
+                    "Upgrade the container runtime, or pick another one with `container_runtime`"
+                }
+                _ => "Install a version inside the manifest's `requirements` range, or pass --disable-checks to skip",
+            }),
+            LumberstackError::Io { .. } | LumberstackError::Interrupted(_) => None,
+        }
+    }
+}
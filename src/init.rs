@@ -1,43 +1,41 @@
-use std::{fs, path::Path, process::exit};
+use std::{fs, path::Path};
 
-use clap::Parser;
-use log::{debug, error, info};
+use log::{debug, info};
 
 use crate::{
-    cli_args::CliArgs, commands::Commands, default_config, logger::Logger, DEFAULT_APP_NAME,
+    default_config,
+    error::{LumberstackError, Result},
+    services::Services,
     DEFAULT_MANIFEST_FILE, DEFAULT_TEMPLATE_DIR,
 };
 
-pub fn initialize() {
-    Logger::init();
-    let args = CliArgs::parse();
-    if args.init {
-        if let Err(_) = fs::write(
-            DEFAULT_MANIFEST_FILE,
-            default_config::generate_default_config(),
-        ) {
-            error!("Error creating init config");
-            exit(exitcode::IOERR)
-        }
-        info!("Default config written!");
-        exit(exitcode::OK);
+/// Writes the default manifest to `./lumberstack.json`
+pub fn write_default_config() -> Result<()> {
+    fs::write(
+        DEFAULT_MANIFEST_FILE,
+        default_config::generate_default_config(),
+    )
+    .map_err(|e| LumberstackError::io("Error creating init config", e))?;
+    info!("Default config written!");
+    Ok(())
+}
+
+/// Removes the downloaded templates, then the app at `app_name` along with the
+/// containers and volumes its build created
+pub fn clean(app_name: &str) -> Result<()> {
+    info!("Cleaning...");
+    if Path::new(DEFAULT_TEMPLATE_DIR).exists() {
+        debug!("Removing template dir");
+        fs::remove_dir_all(DEFAULT_TEMPLATE_DIR)
+            .map_err(|e| LumberstackError::io("Error removing template dir", e))?;
     }
-    if args.clean {
-        info!("Cleaning...");
-        if Path::new(DEFAULT_TEMPLATE_DIR).exists() {
-            debug!("Removing template dir");
-            fs::remove_dir_all(DEFAULT_TEMPLATE_DIR).expect("Dir remove err");
-        }
-        debug!("Removing docker volumes");
-        Commands::exec_raw(
-            DEFAULT_APP_NAME,
-            "docker",
-            &["compose", "down", "-v"],
-            false,
-        );
-        if Path::new(DEFAULT_APP_NAME).exists() {
-            debug!("Removing default app dir");
-            fs::remove_dir_all(DEFAULT_APP_NAME).expect("Dir remove err");
-        }
+    let app_dir = Path::new(app_name);
+    debug!("Removing services");
+    Services::clean(app_dir)?;
+    if app_dir.exists() {
+        debug!("Removing app dir");
+        fs::remove_dir_all(app_dir)
+            .map_err(|e| LumberstackError::io("Error removing app dir", e))?;
     }
+    Ok(())
 }
@@ -0,0 +1,59 @@
+//! Opinionated typescript project generator with a RedwoodJS core.
+//!
+//! ```no_run
+//! use lumberstack::{Lumberstack, Manifest, Options};
+//!
+//! let manifest = Manifest::load(None, Some("myapp"))?;
+//! Lumberstack::builder()
+//!     .manifest(manifest)
+//!     .options(Options {
+//!         only: vec!["templates".to_string()],
+//!         disable_checks: true,
+//!         ..Default::default()
+//!     })
+//!     .build()?
+//!     .run()?;
+//! # Ok::<(), lumberstack::LumberstackError>(())
+//! ```
+
+extern crate fs_extra;
+extern crate log;
+
+mod command_log;
+mod commands;
+pub mod conflicts;
+pub mod container;
+pub mod default_config;
+pub mod doctor;
+pub mod error;
+pub mod init;
+mod lumberstack;
+pub mod manifest;
+mod merge;
+pub mod package_manager;
+mod patches;
+pub mod plan;
+mod recorder;
+pub mod report;
+pub mod reporter;
+pub mod requirements;
+pub mod services;
+pub mod signals;
+pub mod spinner;
+mod sys_checks;
+mod template_ignore;
+mod templates;
+pub mod timings;
+mod transaction;
+mod wait_for;
+
+pub use crate::container::ContainerRuntime;
+pub use crate::error::{LumberstackError, Result};
+pub use crate::lumberstack::{Lumberstack, LumberstackBuilder, Options};
+pub use crate::manifest::Manifest;
+pub use crate::package_manager::PackageManager;
+pub use crate::reporter::Reporter;
+
+pub static DEFAULT_TEMPLATE_DIR: &str = "templates";
+pub static DEFAULT_APP_NAME: &str = "myapp";
+pub static DEFAULT_MANIFEST_FILE: &str = "lumberstack.json";
@@ -1,16 +1,13 @@
-use super::cli_args::CliArgs;
-use clap::Parser;
 use env_logger::fmt::Color;
-use log::Level;
+use log::{Level, LevelFilter};
 use std::io::Write;
 
 pub struct Logger;
 
 impl Logger {
-    pub fn init() {
-        let args = CliArgs::parse();
+    pub fn init(level: LevelFilter) {
         env_logger::Builder::new()
-            .filter_level(args.verbose.log_level_filter())
+            .filter_level(level)
             .format(|buf, record| {
                 let mut buf_style = buf.style();
                 match record.level() {
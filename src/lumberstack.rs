@@ -1,70 +1,234 @@
 use crate::commands::Commands;
-use crate::manifest::{CommandItem, Manifest, TemplateItem};
+use crate::error::{LumberstackError, Result};
+use crate::manifest::{BuildItem, CommandItem, Manifest, PatchItem, TemplateItem, WaitItem};
+use crate::patches::Patches;
+use crate::recorder::Recorder;
+use crate::report::BuildReport;
+use crate::reporter::{BuildSummary, Event, Reporter, SilentReporter};
+use crate::services::Services;
+use crate::signals::Signals;
+use crate::sys_checks::System;
 use crate::templates::Templates;
+use crate::timings::TimingHistory;
+use crate::wait_for::WaitFor;
 
-use super::cli_args::CliArgs;
-use clap::Parser;
-use indicatif::ProgressBar;
-use serde_json::{Error, Map, Value};
+use log::{debug, warn};
+use serde_json::{Map, Value};
+use std::{path::Path, time::Instant};
 
-pub struct Lumberstack;
+/// Run options that used to come straight from the command line
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Only run build items with these tags, all of them when empty
+    pub only: Vec<String>,
+    pub disable_checks: bool,
+    /// Write `.lumberstack/report.md` next to `report.json`
+    pub markdown_report: bool,
+}
+
+pub struct Lumberstack {
+    manifest: Manifest,
+    options: Options,
+    reporter: Box<dyn Reporter>,
+}
+
+#[derive(Default)]
+pub struct LumberstackBuilder {
+    manifest: Option<Manifest>,
+    options: Options,
+    reporter: Option<Box<dyn Reporter>>,
+}
+
+impl LumberstackBuilder {
+    pub fn manifest(mut self, manifest: Manifest) -> Self {
+        self.manifest = Some(manifest);
+        self
+    }
+
+    pub fn options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn reporter(mut self, reporter: Box<dyn Reporter>) -> Self {
+        self.reporter = Some(reporter);
+        self
+    }
+
+    pub fn build(self) -> Result<Lumberstack> {
+        let manifest = self
+            .manifest
+            .ok_or_else(|| LumberstackError::manifest("builder", "no manifest was given"))?;
+
+        Ok(Lumberstack {
+            manifest,
+            options: self.options,
+            // nothing to draw on when embedded without a reporter
+            reporter: self.reporter.unwrap_or_else(|| Box::new(SilentReporter)),
+        })
+    }
+}
 
 impl Lumberstack {
-    pub fn run(manifest: &Manifest, spinner: &ProgressBar) {
-        let manifest_json = &manifest.json;
-        let builder_items = &manifest_json.builder;
-        let app_name = &manifest.app_name;
-
-        let only_run_these = Self::only_run();
-
-        let items: Vec<Map<String, Value>> = builder_items
-            .iter()
-            .filter(|item| {
-                return only_run_these.contains(&item.tag) || only_run_these.is_empty();
-            })
-            .map(serde_json::to_value)
-            .map(Result::unwrap)
-            .map(|r| r.as_object().cloned())
-            .map(Option::unwrap)
-            .collect();
-
-        for item in items {
-            Self::process_item(app_name, item, spinner);
+    pub fn builder() -> LumberstackBuilder {
+        LumberstackBuilder::default()
+    }
+
+    /// Runs the build, reporting `BuildFinished` or `BuildFailed` at the end
+    pub fn run(&self) -> Result<()> {
+        let recorder = Recorder::new(self.reporter.as_ref());
+        let result = self.build(&recorder);
+        if let Some(services) = &self.manifest.json.services {
+            Services::stop(&self.manifest.app_name, services, &recorder);
+        }
+
+        let summary = recorder.summary();
+        self.write_report(&recorder, &summary, &result);
+        Self::report_timings(&recorder);
+        match &result {
+            Ok(()) => recorder.report(&Event::BuildFinished { summary }),
+            Err(e) => recorder.report(&Event::BuildFailed {
+                error: e.to_string(),
+                hint: e.hint().map(String::from),
+                summary,
+            }),
+        }
+        result
+    }
+
+    /// Compares timings with the last runs and adds this one to the history
+    fn report_timings(recorder: &Recorder) {
+        let steps = recorder.steps();
+        let history = TimingHistory::load();
+        recorder.report(&Event::Timings {
+            rows: history.rows(&steps),
+        });
+        if let Err(e) = history.save(&steps) {
+            warn!("Could not save timing history - {}", e);
+        }
+    }
+
+    /// Leaves a record of how the app was generated, a build never fails over it
+    fn write_report(&self, recorder: &Recorder, summary: &BuildSummary, result: &Result<()>) {
+        let app_dir = Path::new(&self.manifest.app_name);
+        if !app_dir.is_dir() {
+            debug!("no {} dir to write a report into", app_dir.display());
+            return;
         }
 
-        spinner.set_prefix("✅");
-        spinner.finish_with_message("Finished!")
+        let report = BuildReport::new(
+            &self.manifest,
+            recorder.steps(),
+            summary.clone(),
+            result.as_ref().err().map(|e| e.to_string()),
+        );
+        match report.write(app_dir, self.options.markdown_report) {
+            Ok(path) => recorder.report(&Event::ReportWritten {
+                path: path.display().to_string(),
+            }),
+            Err(e) => warn!("Could not write build report - {}", e),
+        }
     }
 
-    fn only_run() -> Vec<String> {
-        let args = CliArgs::parse();
-        let only = args.only.unwrap_or(vec![]);
-        return only;
+    fn build(&self, reporter: &dyn Reporter) -> Result<()> {
+        let manifest_json = &self.manifest.json;
+        let builder_items = &manifest_json.builder;
+
+        let only_run_these = &self.options.only;
+        self.manifest.check_tags(only_run_these)?;
+
+        let mut items: Vec<(&BuildItem, Map<String, Value>)> = vec![];
+        for item in builder_items.iter() {
+            if !(only_run_these.contains(&item.tag) || only_run_these.is_empty()) {
+                reporter.report(&Event::StepSkipped {
+                    tag: item.tag.to_owned(),
+                });
+                continue;
+            }
+
+            match serde_json::to_value(item) {
+                Ok(Value::Object(map)) => items.push((item, map)),
+                _ => {
+                    return Err(LumberstackError::manifest(
+                        &item.tag,
+                        "build item is not an object",
+                    ))
+                }
+            }
+        }
+
+        if !self.options.disable_checks {
+            let selected: Vec<&BuildItem> = items.iter().map(|(item, _)| *item).collect();
+            System::check_prerequsites(&self.manifest, &selected, reporter)?;
+        }
+
+        for (item, map) in items {
+            Signals::check()?;
+            reporter.report(&Event::StepStarted {
+                tag: item.tag.to_owned(),
+                feedback: item.feedback.to_owned(),
+            });
+            let started = Instant::now();
+            self.process_item(&item.tag, map, reporter)?;
+            reporter.report(&Event::StepFinished {
+                tag: item.tag.to_owned(),
+                duration_ms: started.elapsed().as_millis() as u64,
+            });
+        }
+        Ok(())
     }
 
-    fn process_item(app_name: &String, item: Map<String, Value>, spinner: &ProgressBar) {
+    fn process_item(
+        &self,
+        tag: &str,
+        item: Map<String, Value>,
+        reporter: &dyn Reporter,
+    ) -> Result<()> {
+        let app_name = &self.manifest.app_name;
+        let runtime = self.manifest.container_runtime;
+        let on_conflict = self.manifest.json.on_conflict.unwrap_or_default();
+        let services = self.manifest.json.services.as_deref().unwrap_or_default();
+
         // Process items in order
         for (k, v) in item.iter() {
+            if k.contains("services") {
+                let names: serde_json::Result<Vec<String>> = serde_json::from_value(v.to_owned());
+                if let Ok(n) = names {
+                    Services::start(runtime, app_name, &n, services, reporter)?;
+                }
+            }
+
+            if k.contains("wait_for") {
+                let waits: serde_json::Result<Vec<WaitItem>> = serde_json::from_value(v.to_owned());
+                if let Ok(w) = waits {
+                    WaitFor::process(w, reporter)?;
+                }
+            }
+
             if k.contains("commands") {
-                let commands: Result<Vec<CommandItem>, Error> =
+                let commands: serde_json::Result<Vec<CommandItem>> =
                     serde_json::from_value(v.to_owned());
                 if let Ok(c) = commands {
-                    Commands::process(c, spinner);
+                    Commands::process(tag, c, reporter)?;
                 }
             }
 
             if k.contains("templates") {
-                let templates: Result<Vec<TemplateItem>, Error> =
+                let templates: serde_json::Result<Vec<TemplateItem>> =
                     serde_json::from_value(v.to_owned());
                 if let Ok(t) = templates {
-                    Templates::process(app_name, t, spinner);
+                    Templates::process(app_name, t, on_conflict, reporter)?;
                 }
             }
 
-            if k.contains("feedback") {
-                let v: Result<String, Error> = serde_json::from_value(v.to_owned());
-                spinner.set_message(v.unwrap_or(String::from("UNKNOWN")));
+            if k.contains("patches") {
+                let patches: serde_json::Result<Vec<PatchItem>> =
+                    serde_json::from_value(v.to_owned());
+                if let Ok(p) = patches {
+                    Patches::process(p, reporter)?;
+                }
             }
         }
+        Ok(())
     }
 }
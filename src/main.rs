@@ -1,36 +1,153 @@
-// #![allow(unused)]
-extern crate fs_extra;
-extern crate log;
-
 mod cli_args;
-mod commands;
-mod default_config;
-mod init;
 mod logger;
-mod lumberstack;
-mod manifest;
-mod spinner;
-mod sys_checks;
-mod templates;
-
-use lumberstack::Lumberstack;
-use manifest::Manifest;
-use spinner::create_spinner;
-use sys_checks::System;
 
-pub static DEFAULT_TEMPLATE_DIR: &'static str = "templates";
-pub static DEFAULT_APP_NAME: &'static str = "myapp";
-pub static DEFAULT_MANIFEST_FILE: &'static str = "lumberstack.json";
+use cli_args::{BuildArgs, CliArgs, Command, Output};
+use log::error;
+use logger::Logger;
+use lumberstack::doctor::Doctor;
+use lumberstack::init;
+use lumberstack::plan::Plan;
+use lumberstack::reporter::{
+    print_line, BuildSummary, Event, JsonReporter, PlainReporter, SpinnerReporter,
+};
+use lumberstack::signals::{Signals, Terminal};
+use lumberstack::{
+    Lumberstack, LumberstackError, Manifest, Options, Reporter, DEFAULT_MANIFEST_FILE,
+};
+use std::process::exit;
 
 fn main() {
-    init::initialize();
+    let args = CliArgs::parse_args();
+    Logger::init(args.verbose.log_level_filter());
+    if let Err(e) = Signals::install() {
+        fail(e);
+    }
+
+    let config = args.config.as_deref();
+    // `lumberstack <NAME>` is `lumberstack new <NAME>`
+    let command = args.command.unwrap_or(Command::New {
+        name: args.name,
+        only: args.only,
+        build: args.build,
+    });
+
+    match command {
+        Command::New { name, only, build } => run_build(config, name, only, build),
+        Command::Run { tags, name, build } => run_build(config, name, tags, build),
+        Command::Init => {
+            if let Err(e) = init::write_default_config() {
+                fail(e);
+            }
+            print_line(format!(
+                "⚙️ Default manifest written to {}",
+                DEFAULT_MANIFEST_FILE
+            ));
+        }
+        Command::Clean { name } => {
+            let manifest = load(config, name.as_deref());
+            if let Err(e) = init::clean(&manifest.app_name) {
+                fail(e);
+            }
+            print_line(format!("🧹 Cleaned {}", manifest.app_name));
+        }
+        Command::Plan { tags, name } => {
+            let manifest = load(config, name.as_deref());
+            if let Err(e) = manifest.check_tags(&tags) {
+                fail(e);
+            }
+            Plan::lines(&manifest, &tags).iter().for_each(print_line);
+        }
+        Command::Validate => {
+            let manifest = load(config, None);
+            if let Err(e) = manifest.validate() {
+                fail(e);
+            }
+            print_line(format!(
+                "✅ {} is valid, {} steps",
+                manifest.source,
+                manifest.json.builder.len()
+            ));
+        }
+        Command::Doctor { name, json } => {
+            let diagnosis = Doctor::diagnose(config, name.as_deref());
+            match json {
+                true => match serde_json::to_string_pretty(&diagnosis) {
+                    Ok(json) => print_line(json),
+                    Err(e) => error!("❌ Could not serialize the report - {}", e),
+                },
+                false => diagnosis.lines().iter().for_each(print_line),
+            }
+            if !diagnosis.ok() {
+                exit(exitcode::UNAVAILABLE);
+            }
+        }
+        Command::List => {
+            let manifest = load(config, None);
+            let width = manifest
+                .json
+                .builder
+                .iter()
+                .map(|item| item.tag.len())
+                .max()
+                .unwrap_or_default();
+            for item in manifest.json.builder.iter() {
+                print_line(format!(
+                    "{:<width$}  {}",
+                    item.tag,
+                    item.feedback,
+                    width = width
+                ));
+            }
+        }
+    }
+}
 
-    let spinner = create_spinner();
-    System::check_prerequsites(&spinner);
+fn run_build(config: Option<&str>, name: Option<String>, only: Vec<String>, build: BuildArgs) {
+    let reporter: Box<dyn Reporter> = match build.output {
+        Output::Spinner => Box::new(SpinnerReporter::new()),
+        Output::Plain => Box::<PlainReporter>::default(),
+        Output::Json => Box::new(JsonReporter),
+    };
 
-    let manifest = Manifest::new();
-    Lumberstack::run(&manifest, &spinner);
+    let manifest = match Manifest::load(config, name.as_deref()) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            reporter.report(&Event::BuildFailed {
+                error: e.to_string(),
+                hint: e.hint().map(String::from),
+                summary: BuildSummary::default(),
+            });
+            fail(e);
+        }
+    };
+
+    // the build reports its own finish or failure
+    let result = Lumberstack::builder()
+        .manifest(manifest)
+        .options(Options {
+            only,
+            disable_checks: build.disable_checks,
+            markdown_report: build.markdown_report,
+        })
+        .reporter(reporter)
+        .build()
+        .and_then(|lumberstack| lumberstack.run());
+
+    if let Err(e) = result {
+        fail(e);
+    }
+}
+
+fn load(config: Option<&str>, name: Option<&str>) -> Manifest {
+    Manifest::load(config, name).unwrap_or_else(|e| fail(e))
+}
 
-    spinner.set_prefix("✅");
-    spinner.finish_with_message("Lumberstack Complete!");
+fn fail(e: LumberstackError) -> ! {
+    // a stopped command may have left the terminal in raw mode
+    Terminal::restore();
+    error!("❌ {}", e);
+    if let Some(hint) = e.hint() {
+        error!("💡 {}", hint);
+    }
+    exit(e.exit_code());
 }
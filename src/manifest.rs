@@ -1,13 +1,32 @@
-use clap::Parser;
 use log::info;
-use serde::{Deserialize, Serialize};
-use std::{collections::BTreeMap, fs};
+use regex::Regex;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, fs, path::Path};
 
-use crate::{cli_args::CliArgs, default_config::generate_default_config, DEFAULT_APP_NAME, DEFAULT_MANIFEST_FILE};
+use crate::{
+    container::ContainerRuntime,
+    default_config::generate_default_config,
+    error::{LumberstackError, Result},
+    package_manager::PackageManager,
+    requirements::Requirements,
+    DEFAULT_APP_NAME, DEFAULT_MANIFEST_FILE,
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ManifestJson {
     pub app_name: Option<String>,
+    pub on_conflict: Option<ConflictPolicy>,
+    /// docker, podman or nerdctl, detected when left out
+    pub container_runtime: Option<ContainerRuntime>,
+    /// yarn, yarn-berry, npm or pnpm, detected from lockfiles when left out
+    pub package_manager: Option<PackageManager>,
+    /// Semver ranges tools have to be in, `{ "node": ">=18 <21" }`
+    pub requirements: Option<BTreeMap<String, String>>,
+    /// Custom checks run before every build
+    pub checks: Option<Vec<CheckItem>>,
+    /// Compose services build steps can start by name
+    pub services: Option<Vec<ServiceItem>>,
     pub builder: Vec<BuildItem>,
 }
 
@@ -16,8 +35,17 @@ pub struct BuildItem {
     pub tag: String,
     pub feedback: String,
     pub context: Option<String>,
+    /// Tools the step runs, only checked when the step is selected
+    pub requires: Option<Vec<String>>,
+    /// Custom checks for this step, run with the system checks when it's selected
+    pub checks: Option<Vec<CheckItem>>,
+    /// Names of `services` started before the rest of the step runs
+    pub services: Option<Vec<String>>,
+    /// Probes that must pass before the rest of the step runs
+    pub wait_for: Option<Vec<WaitItem>>,
     pub commands: Option<Vec<CommandItem>>,
     pub templates: Option<Vec<TemplateItem>>,
+    pub patches: Option<Vec<PatchItem>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -26,6 +54,145 @@ pub struct TemplateItem {
     pub source: String,
     pub dest: String,
     pub replace_map: Option<BTreeMap<String, String>>,
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub features: Option<Vec<String>>,
+    #[serde(alias = "binary_globs")]
+    pub raw_globs: Option<Vec<String>>,
+    pub follow_symlinks: Option<bool>,
+    pub on_conflict: Option<ConflictPolicy>,
+    pub array_strategy: Option<ArrayStrategy>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ArrayStrategy {
+    Append,
+    #[default]
+    Replace,
+    Union,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    #[default]
+    Overwrite,
+    Skip,
+    Error,
+    Backup,
+    Prompt,
+    Merge,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PatchItem {
+    pub feedback: Option<String>,
+    pub file: String,
+    #[serde(flatten)]
+    pub action: PatchAction,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PatchAction {
+    InsertAfter {
+        pattern: String,
+        content: String,
+    },
+    InsertBefore {
+        pattern: String,
+        content: String,
+    },
+    ReplaceBetween {
+        start: String,
+        end: String,
+        content: String,
+    },
+    Append {
+        content: String,
+    },
+    Prepend {
+        content: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServiceItem {
+    /// The service's name in the compose file
+    pub name: String,
+    /// Directory holding the compose file, the app dir by default
+    pub context: Option<String>,
+    pub compose_file: Option<String>,
+    /// Host ports that have to be free before the service is started
+    pub ports: Option<Vec<u16>>,
+    /// Leave the service running once the build is done (default true)
+    pub keep_running: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WaitItem {
+    pub feedback: Option<String>,
+    #[serde(flatten)]
+    pub probe: Probe,
+    /// Where a `command` probe runs
+    pub context: Option<String>,
+    /// Seconds between probes
+    pub interval: Option<u64>,
+    /// Seconds before giving up and failing the build
+    pub timeout: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Probe {
+    /// `host:port` accepts connections
+    Tcp(String),
+    /// `http://` url answers 200
+    Http(String),
+    /// Path exists
+    File(String),
+    /// Command exits 0
+    Command(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CheckItem {
+    /// Shown when the check fails
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub check: Check,
+    /// Regex a `command` check's output has to match, otherwise it has to exit 0
+    pub expect: Option<String>,
+    /// Where `free_disk_mb` is measured, default `.`
+    pub path: Option<String>,
+}
+
+impl CheckItem {
+    /// `name`, or what's being checked
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.to_owned(),
+            None => match &self.check {
+                Check::Command(command) => format!("`{}`", command),
+                Check::Env(var) => format!("${}", var),
+                Check::File(path) => path.to_owned(),
+                Check::FreeDiskMb(_) => "free disk space".to_string(),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Check {
+    Command(String),
+    /// Environment variable is set and not empty
+    Env(String),
+    /// Path exists
+    File(String),
+    /// Megabytes that have to be free
+    FreeDiskMb(u64),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -33,58 +200,199 @@ pub struct CommandItem {
     pub feedback: Option<String>,
     pub command: String,
     pub context: Option<String>,
+    /// Seconds before the command is stopped and the build fails
+    pub timeout: Option<u64>,
+    /// Extra attempts after the first one fails
+    pub retries: Option<u32>,
+    /// Seconds before the first retry, doubled for every one after
+    pub retry_delay: Option<u64>,
+    /// Only retry when a line of the failed attempt's output matches this regex
+    pub retry_on_output: Option<String>,
 }
 #[derive(Debug, Clone)]
-
 pub struct Manifest {
     pub app_name: String,
     pub json: ManifestJson,
+    /// Where the manifest was read from
+    pub source: String,
+    /// sha256 of the manifest as written, before `{{app_name}}` is filled in
+    pub sha256: String,
+    pub container_runtime: ContainerRuntime,
+    pub package_manager: PackageManager,
 }
 
 impl Manifest {
-    pub fn new() -> Manifest {
-        let tmp_app_name = Self::tmp_app_name();
-        let manifest_str = Self::read_manifest();
+    /// Loads `config`, `./lumberstack.json` or the built in default, in that order.
+    /// `name` is the project name and path substituted for `{{app_name}}`.
+    pub fn load(config: Option<&str>, name: Option<&str>) -> Result<Manifest> {
+        let (manifest_path, manifest_str) = Self::read_manifest(config)?;
+        Self::parse(&manifest_path, &manifest_str, name)
+    }
 
-        let processed_manifest = manifest_str.replace("{{app_name}}", &tmp_app_name);
+    /// Parses manifest json, `source` names where it came from in error messages
+    pub fn parse(source: &str, manifest_str: &str, name: Option<&str>) -> Result<Manifest> {
+        // tools have to be known before `{{container_cli}}` and `{{pm}}` can be filled in
+        let raw: serde_json::Value = serde_json::from_str(manifest_str)
+            .map_err(|e| LumberstackError::manifest(source, e))?;
+        let container_runtime =
+            ContainerRuntime::resolve(Self::setting(&raw, "container_runtime", source)?);
+        // a name given on the command line wins over the manifest's, it's where the app goes
+        let manifest_app_name = raw
+            .get("app_name")
+            .and_then(|a| a.as_str())
+            .filter(|a| !a.contains("{{app_name}}"));
+        let app_name = name
+            .or(manifest_app_name)
+            .unwrap_or(DEFAULT_APP_NAME)
+            .to_string();
+        let package_manager = PackageManager::resolve(
+            Self::setting(&raw, "package_manager", source)?,
+            &[Path::new(&app_name), Path::new(".")],
+        );
 
-        let manifest: ManifestJson =
-        serde_json::from_str(&processed_manifest).expect("Error reading json");
+        // the name lands inside json strings, quotes and backslashes in it need escaping
+        let escaped_app_name = serde_json::to_string(&app_name).expect("strings serialize");
+        let escaped_app_name = &escaped_app_name[1..escaped_app_name.len() - 1];
+        let mut processed_manifest = manifest_str
+            .replace("{{app_name}}", escaped_app_name)
+            .replace("{{container_cli}}", container_runtime.cli());
+        for (var, value) in package_manager.vars() {
+            let var = format!("{{{{{}}}}}", var);
+            // a variable that's empty for this manager takes its separating space with it
+            if value.is_empty() {
+                processed_manifest = processed_manifest.replace(&format!(" {}", var), "");
+            }
+            processed_manifest = processed_manifest.replace(&var, value);
+        }
 
-        let app_name = manifest.app_name.clone().unwrap_or(DEFAULT_APP_NAME.to_string());
+        let mut manifest: ManifestJson = serde_json::from_str(&processed_manifest)
+            .map_err(|e| LumberstackError::manifest(source, e))?;
+        manifest.app_name = Some(app_name.clone());
+        for (tool, range) in manifest.requirements.iter().flatten() {
+            Requirements::parse(range).map_err(|e| {
+                LumberstackError::manifest(source, format!("requirements.{} - {}", tool, e))
+            })?;
+        }
 
-        Manifest {
+        Ok(Manifest {
             app_name,
             json: manifest,
-        }
+            source: source.to_string(),
+            sha256: format!("{:x}", Sha256::digest(manifest_str)),
+            container_runtime,
+            package_manager,
+        })
     }
 
-    fn read_manifest() -> String {
-        let args = CliArgs::parse();
-        match &args.config {
-            Some(conf) => {
-                let config = fs::read_to_string(conf).expect("Error reading users manifest");
+    /// Catches what parsing can't: repeated tags, steps starting services that aren't
+    /// defined and regexes that don't compile
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: String| LumberstackError::manifest(&self.source, message);
+        let regex = |tag: &str, field: &str, pattern: &str| {
+            Regex::new(pattern)
+                .map(|_| ())
+                .map_err(|e| invalid(format!("[{}] {} - {}", tag, field, e)))
+        };
+        let services = self.json.services.as_deref().unwrap_or_default();
 
-                return config;
+        for check in self.json.checks.iter().flatten() {
+            if let Some(expect) = &check.expect {
+                regex("checks", "expect", expect)?;
             }
-            None => {
-                if fs::metadata(DEFAULT_MANIFEST_FILE).is_ok() {
-                    info!("⚙️ Found a default manifest!");
-                    return fs::read_to_string(DEFAULT_MANIFEST_FILE)
-                        .expect("Error reading default manifest");
+        }
+
+        let mut tags: Vec<&str> = vec![];
+        for item in self.json.builder.iter() {
+            let tag = item.tag.as_str();
+            if tags.contains(&tag) {
+                return Err(invalid(format!(
+                    "tag {} is used by more than one step",
+                    tag
+                )));
+            }
+            tags.push(tag);
+
+            for name in item.services.iter().flatten() {
+                if !services.iter().any(|s| &s.name == name) {
+                    return Err(invalid(format!(
+                        "[{}] service {} is not defined in `services`",
+                        tag, name
+                    )));
+                }
+            }
+            for check in item.checks.iter().flatten() {
+                if let Some(expect) = &check.expect {
+                    regex(tag, "expect", expect)?;
                 }
-                return generate_default_config().to_string();
             }
+            for command in item.commands.iter().flatten() {
+                if let Some(retry_on) = &command.retry_on_output {
+                    regex(tag, "retry_on_output", retry_on)?;
+                }
+            }
+            for patch in item.patches.iter().flatten() {
+                match &patch.action {
+                    PatchAction::InsertAfter { pattern, .. }
+                    | PatchAction::InsertBefore { pattern, .. } => regex(tag, "pattern", pattern)?,
+                    PatchAction::ReplaceBetween { start, end, .. } => {
+                        regex(tag, "start", start)?;
+                        regex(tag, "end", end)?;
+                    }
+                    PatchAction::Append { .. } | PatchAction::Prepend { .. } => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails on the first of `tags` no step has
+    pub fn check_tags(&self, tags: &[String]) -> Result<()> {
+        match tags
+            .iter()
+            .find(|tag| !self.json.builder.iter().any(|item| &&item.tag == tag))
+        {
+            Some(tag) => Err(LumberstackError::manifest(
+                &self.source,
+                format!("no step is tagged {}", tag),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// A top level setting that's needed before the whole manifest can be parsed
+    fn setting<T: DeserializeOwned>(
+        raw: &serde_json::Value,
+        key: &str,
+        source: &str,
+    ) -> Result<Option<T>> {
+        match raw.get(key) {
+            Some(value) => serde_json::from_value(value.to_owned())
+                .map(Some)
+                .map_err(|e| LumberstackError::manifest(source, format!("{} - {}", key, e))),
+            None => Ok(None),
         }
     }
 
-    pub fn tmp_app_name() -> String {
-        let args = CliArgs::parse();
-        match &args.name {
-            Some(name) => {
-                return name.to_owned();
+    /// Returns where the manifest came from along with its contents
+    fn read_manifest(config: Option<&str>) -> Result<(String, String)> {
+        match config {
+            Some(conf) => {
+                let config =
+                    fs::read_to_string(conf).map_err(|e| LumberstackError::manifest(conf, e))?;
+                Ok((conf.to_owned(), config))
+            }
+            None => {
+                if fs::metadata(DEFAULT_MANIFEST_FILE).is_ok() {
+                    info!("⚙️ Found a default manifest!");
+                    let config = fs::read_to_string(DEFAULT_MANIFEST_FILE)
+                        .map_err(|e| LumberstackError::manifest(DEFAULT_MANIFEST_FILE, e))?;
+                    return Ok((DEFAULT_MANIFEST_FILE.to_string(), config));
+                }
+                Ok((
+                    "default config".to_string(),
+                    generate_default_config().to_string(),
+                ))
             }
-            None => String::from(DEFAULT_APP_NAME),
         }
     }
 }
@@ -0,0 +1,275 @@
+use crate::manifest::ArrayStrategy;
+use serde_json::Value;
+use std::{error::Error, path::Path};
+
+enum Format {
+    Json,
+    Yaml,
+    Toml,
+}
+
+pub struct Merge;
+
+impl Merge {
+    /// Deep merges `incoming` into `existing` for structured files (json, yaml, toml).
+    /// Returns `None` when the file type can't be merged.
+    pub fn merge(
+        path: &Path,
+        existing: &[u8],
+        incoming: &[u8],
+        arrays: ArrayStrategy,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let format = match Self::format(path) {
+            Some(format) => format,
+            None => return Ok(None),
+        };
+
+        let mut base = Self::parse(&format, existing)
+            .map_err(|e| format!("Error parsing {} - {}", path.display(), e))?;
+        let overlay = Self::parse(&format, incoming)
+            .map_err(|e| format!("Error parsing template for {} - {}", path.display(), e))?;
+
+        Self::deep_merge(&mut base, overlay, arrays);
+
+        let merged = Self::serialize(&format, &base)
+            .map_err(|e| format!("Error writing merged {} - {}", path.display(), e))?;
+        Ok(Some(merged.into_bytes()))
+    }
+
+    fn format(path: &Path) -> Option<Format> {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        match extension {
+            "json" => Some(Format::Json),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "toml" => Some(Format::Toml),
+            _ => None,
+        }
+    }
+
+    // Every format goes through serde_json's Value so there's a single merge implementation
+    fn parse(format: &Format, bytes: &[u8]) -> Result<Value, Box<dyn Error>> {
+        let value = match format {
+            Format::Json => serde_json::from_str(&Self::strip_jsonc(std::str::from_utf8(bytes)?))?,
+            Format::Yaml => serde_yaml::from_slice(bytes)?,
+            Format::Toml => toml::from_str(std::str::from_utf8(bytes)?)?,
+        };
+        Ok(value)
+    }
+
+    /// Drops the comments and trailing commas JSONC allows (`.vscode/settings.json`,
+    /// `tsconfig.json`), leaving strings alone
+    fn strip_jsonc(text: &str) -> String {
+        let mut uncommented = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => {
+                    uncommented.push(c);
+                    Self::copy_string(&mut chars, &mut uncommented);
+                }
+                '/' if chars.peek() == Some(&'/') => {
+                    while chars.next_if(|c| *c != '\n').is_some() {}
+                }
+                '/' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    let mut last = ' ';
+                    for c in chars.by_ref() {
+                        if last == '*' && c == '/' {
+                            break;
+                        }
+                        last = c;
+                    }
+                    uncommented.push(' ');
+                }
+                c => uncommented.push(c),
+            }
+        }
+
+        let mut stripped = String::with_capacity(uncommented.len());
+        let mut chars = uncommented.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => {
+                    stripped.push(c);
+                    Self::copy_string(&mut chars, &mut stripped);
+                }
+                ',' => {
+                    let next = chars.clone().find(|c| !c.is_whitespace());
+                    if !matches!(next, Some('}' | ']')) {
+                        stripped.push(c);
+                    }
+                }
+                c => stripped.push(c),
+            }
+        }
+        stripped
+    }
+
+    /// Copies the rest of a json string, up to and including its closing quote
+    fn copy_string(chars: &mut impl Iterator<Item = char>, to: &mut String) {
+        while let Some(c) = chars.next() {
+            to.push(c);
+            match c {
+                '\\' => to.extend(chars.next()),
+                '"' => break,
+                _ => {}
+            }
+        }
+    }
+
+    fn serialize(format: &Format, value: &Value) -> Result<String, Box<dyn Error>> {
+        let serialized = match format {
+            Format::Json => {
+                let mut json = serde_json::to_string_pretty(value)?;
+                json.push('\n');
+                json
+            }
+            Format::Yaml => serde_yaml::to_string(value)?,
+            Format::Toml => toml::to_string(value)?,
+        };
+        Ok(serialized)
+    }
+
+    fn deep_merge(base: &mut Value, overlay: Value, arrays: ArrayStrategy) {
+        match (base, overlay) {
+            (Value::Object(base), Value::Object(overlay)) => {
+                for (key, value) in overlay {
+                    match base.get_mut(&key) {
+                        Some(existing) => Self::deep_merge(existing, value, arrays),
+                        None => {
+                            base.insert(key, value);
+                        }
+                    }
+                }
+            }
+            (Value::Array(base), Value::Array(overlay)) => match arrays {
+                ArrayStrategy::Replace => *base = overlay,
+                ArrayStrategy::Append => base.extend(overlay),
+                ArrayStrategy::Union => {
+                    for value in overlay {
+                        if !base.contains(&value) {
+                            base.push(value);
+                        }
+                    }
+                }
+            },
+            (base, overlay) => *base = overlay,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn merged(base: Value, overlay: Value, arrays: ArrayStrategy) -> Value {
+        let mut base = base;
+        Merge::deep_merge(&mut base, overlay, arrays);
+        base
+    }
+
+    fn merge_file(name: &str, existing: &str, incoming: &str) -> String {
+        let merged = Merge::merge(
+            Path::new(name),
+            existing.as_bytes(),
+            incoming.as_bytes(),
+            ArrayStrategy::Replace,
+        )
+        .unwrap()
+        .unwrap();
+        String::from_utf8(merged).unwrap()
+    }
+
+    #[test]
+    fn objects_merge_key_by_key() {
+        assert_eq!(
+            merged(
+                json!({"a": 1, "nested": {"keep": true, "set": 1}}),
+                json!({"b": 2, "nested": {"set": 2}}),
+                ArrayStrategy::Replace
+            ),
+            json!({"a": 1, "b": 2, "nested": {"keep": true, "set": 2}})
+        );
+    }
+
+    #[test]
+    fn arrays_replace() {
+        assert_eq!(
+            merged(
+                json!({"x": [1, 2]}),
+                json!({"x": [2, 3]}),
+                ArrayStrategy::Replace
+            ),
+            json!({"x": [2, 3]})
+        );
+    }
+
+    #[test]
+    fn arrays_append() {
+        assert_eq!(
+            merged(
+                json!({"x": [1, 2]}),
+                json!({"x": [2, 3]}),
+                ArrayStrategy::Append
+            ),
+            json!({"x": [1, 2, 2, 3]})
+        );
+    }
+
+    #[test]
+    fn arrays_union() {
+        assert_eq!(
+            merged(
+                json!({"x": [1, 2]}),
+                json!({"x": [2, 3]}),
+                ArrayStrategy::Union
+            ),
+            json!({"x": [1, 2, 3]})
+        );
+    }
+
+    #[test]
+    fn jsonc_comments_and_trailing_commas() {
+        let existing = r#"{
+  // line comment
+  "editor.tabSize": 2, /* block
+  comment */
+  "url": "http://example.com/*not a comment*/",
+  "list": [1, 2,],
+}
+"#;
+        let merged: Value =
+            serde_json::from_str(&merge_file("settings.json", existing, r#"{"b": true}"#)).unwrap();
+        assert_eq!(
+            merged,
+            json!({
+                "editor.tabSize": 2,
+                "url": "http://example.com/*not a comment*/",
+                "list": [1, 2],
+                "b": true
+            })
+        );
+    }
+
+    #[test]
+    fn escaped_quotes_stay_in_strings() {
+        let merged: Value =
+            serde_json::from_str(&merge_file("a.json", r#"{"q": "a \"//\" b",}"#, "{}")).unwrap();
+        assert_eq!(merged, json!({"q": "a \"//\" b"}));
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        let existing =
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\n\n[dependencies]\nserde = \"1\"\n";
+        let incoming = "[dependencies]\nlog = \"0.4\"\n";
+        let merged: toml::Value =
+            toml::from_str(&merge_file("Cargo.toml", existing, incoming)).unwrap();
+        let expected: toml::Value = toml::from_str(
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\n\n[dependencies]\nserde = \"1\"\nlog = \"0.4\"\n",
+        )
+        .unwrap();
+        assert_eq!(merged, expected);
+    }
+}
@@ -0,0 +1,91 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{path::Path, process::Command};
+
+/// The package manager generated apps are driven with
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PackageManager {
+    #[default]
+    #[serde(rename = "yarn")]
+    YarnClassic,
+    #[serde(rename = "yarn-berry")]
+    YarnBerry,
+    #[serde(rename = "npm")]
+    Npm,
+    #[serde(rename = "pnpm")]
+    Pnpm,
+}
+
+impl PackageManager {
+    pub fn cli(&self) -> &'static str {
+        match self {
+            PackageManager::YarnClassic | PackageManager::YarnBerry => "yarn",
+            PackageManager::Npm => "npm",
+            PackageManager::Pnpm => "pnpm",
+        }
+    }
+
+    /// `{{pm}}`, `{{pm_run}}`, `{{pm_add}}`, `{{pm_exec}}` and `{{pm_args}}` for manifests.
+    /// `{{pm_args}}` goes before flags meant for a `create` package, npm 7+ keeps them otherwise.
+    pub fn vars(&self) -> [(&'static str, &'static str); 5] {
+        let (run, add, exec) = match self {
+            PackageManager::YarnClassic | PackageManager::YarnBerry => {
+                ("yarn run", "yarn add", "yarn")
+            }
+            PackageManager::Npm => ("npm run", "npm install", "npx"),
+            PackageManager::Pnpm => ("pnpm run", "pnpm add", "pnpm exec"),
+        };
+        [
+            ("pm", self.cli()),
+            ("pm_run", run),
+            ("pm_add", add),
+            ("pm_exec", exec),
+            (
+                "pm_args",
+                if *self == PackageManager::Npm {
+                    "--"
+                } else {
+                    ""
+                },
+            ),
+        ]
+    }
+
+    /// The configured manager, or the one whose lockfile is in `dirs`, falling back to yarn
+    pub fn resolve(configured: Option<PackageManager>, dirs: &[&Path]) -> PackageManager {
+        configured
+            .or_else(|| dirs.iter().find_map(|dir| Self::detect(dir)))
+            .unwrap_or_default()
+    }
+
+    fn detect(dir: &Path) -> Option<PackageManager> {
+        let found = if dir.join("pnpm-lock.yaml").exists() {
+            Some(PackageManager::Pnpm)
+        } else if dir.join("package-lock.json").exists() {
+            Some(PackageManager::Npm)
+        } else if dir.join("yarn.lock").exists() {
+            Some(Self::yarn_flavour(dir))
+        } else {
+            None
+        };
+        debug!("package manager in {}: {:?}", dir.display(), found);
+        found
+    }
+
+    /// Berry projects carry a `.yarnrc.yml`, otherwise ask yarn itself
+    fn yarn_flavour(dir: &Path) -> PackageManager {
+        if dir.join(".yarnrc.yml").exists() {
+            return PackageManager::YarnBerry;
+        }
+        let version = Command::new("yarn")
+            .arg("--version")
+            .current_dir(dir)
+            .output()
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+            .unwrap_or_default();
+        match version.starts_with("1.") || version.is_empty() {
+            true => PackageManager::YarnClassic,
+            false => PackageManager::YarnBerry,
+        }
+    }
+}
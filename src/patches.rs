@@ -0,0 +1,424 @@
+use crate::error::{LumberstackError, Result};
+use crate::manifest::{PatchAction, PatchItem};
+use crate::reporter::{Event, Reporter};
+use log::debug;
+use regex::{Regex, RegexBuilder};
+use std::{error::Error, fs, io::ErrorKind, path::Path};
+
+pub struct Patches;
+
+impl Patches {
+    pub fn process(patches: Vec<PatchItem>, reporter: &dyn Reporter) -> Result<()> {
+        for patch in patches.iter() {
+            if let Some(message) = patch.feedback.to_owned() {
+                reporter.report(&Event::Feedback { message });
+            }
+
+            let changed = Self::apply(patch, &Self::run(&patches, patch)).map_err(|e| {
+                LumberstackError::Patch {
+                    file: patch.file.to_owned(),
+                    message: e.to_string(),
+                }
+            })?;
+            reporter.report(&Event::PatchApplied {
+                file: patch.file.to_owned(),
+                changed,
+            });
+        }
+        Ok(())
+    }
+
+    /// Content of every patch inserting at the same place in the same file as `patch`.
+    /// Later ones land next to earlier ones, so re-runs look through all of them.
+    fn run(patches: &[PatchItem], patch: &PatchItem) -> Vec<String> {
+        let anchor = Self::anchor(&patch.action);
+        patches
+            .iter()
+            .filter(|p| {
+                p.file == patch.file && anchor.is_some() && Self::anchor(&p.action) == anchor
+            })
+            .filter_map(|p| Self::content(&p.action))
+            .map(Self::as_lines)
+            .filter(|block| !block.is_empty())
+            .collect()
+    }
+
+    fn anchor(action: &PatchAction) -> Option<(&'static str, &str)> {
+        match action {
+            PatchAction::InsertAfter { pattern, .. } => Some(("insert_after", pattern)),
+            PatchAction::InsertBefore { pattern, .. } => Some(("insert_before", pattern)),
+            PatchAction::Append { .. } => Some(("append", "")),
+            PatchAction::Prepend { .. } => Some(("prepend", "")),
+            PatchAction::ReplaceBetween { .. } => None,
+        }
+    }
+
+    fn content(action: &PatchAction) -> Option<&str> {
+        match action {
+            PatchAction::InsertAfter { content, .. }
+            | PatchAction::InsertBefore { content, .. }
+            | PatchAction::Append { content }
+            | PatchAction::Prepend { content } => Some(content),
+            PatchAction::ReplaceBetween { .. } => None,
+        }
+    }
+
+    /// Returns whether the file was changed. `run` is the content of the patches sharing
+    /// `patch`'s insertion point, see [`Patches::run`].
+    fn apply(patch: &PatchItem, run: &[String]) -> std::result::Result<bool, Box<dyn Error>> {
+        let path = Path::new(&patch.file);
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            // appending is how new env files get started, everything else needs a target
+            Err(e) if e.kind() == ErrorKind::NotFound && Self::creates_file(&patch.action) => {
+                String::new()
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let patched = match &patch.action {
+            PatchAction::InsertAfter { pattern, content } => {
+                let m = Self::find(pattern, &text)?;
+                let line_end = text[m.1..].find('\n').map_or(text.len(), |i| m.1 + i + 1);
+                Self::insert(&text, line_end, content, run)
+            }
+            PatchAction::InsertBefore { pattern, content } => {
+                let m = Self::find(pattern, &text)?;
+                let line_start = text[..m.0].rfind('\n').map_or(0, |i| i + 1);
+                Self::insert(&text, line_start, content, run)
+            }
+            PatchAction::ReplaceBetween {
+                start,
+                end,
+                content,
+            } => {
+                let start_match = Self::find(start, &text)?;
+                let block_start = text[start_match.1..]
+                    .find('\n')
+                    .map_or(text.len(), |i| start_match.1 + i + 1);
+                let end_match = Self::find(end, &text[block_start..])
+                    .map_err(|_| format!("end marker [{}] not found after [{}]", end, start))?;
+                let block_end = block_start + end_match.0;
+                let block_end = text[..block_end]
+                    .rfind('\n')
+                    .map_or(0, |i| i + 1)
+                    .max(block_start);
+
+                let mut patched = text[..block_start].to_string();
+                patched.push_str(&Self::as_lines(content));
+                patched.push_str(&text[block_end..]);
+                Some(patched)
+            }
+            PatchAction::Append { content } => Self::insert(&text, text.len(), content, run),
+            PatchAction::Prepend { content } => Self::insert(&text, 0, content, run),
+        };
+
+        match patched {
+            Some(patched) if patched != text => {
+                debug!("patching {}", path.display());
+                fs::write(path, patched)?;
+                Ok(true)
+            }
+            _ => {
+                debug!("{} already patched", path.display());
+                Ok(false)
+            }
+        }
+    }
+
+    fn creates_file(action: &PatchAction) -> bool {
+        matches!(
+            action,
+            PatchAction::Append { .. } | PatchAction::Prepend { .. }
+        )
+    }
+
+    fn find(pattern: &str, text: &str) -> std::result::Result<(usize, usize), Box<dyn Error>> {
+        let re: Regex = RegexBuilder::new(pattern).multi_line(true).build()?;
+        match re.find(text) {
+            Some(m) => Ok((m.start(), m.end())),
+            None => Err(format!("pattern [{}] not found", pattern).into()),
+        }
+    }
+
+    // Returns None when the content is already at `at` so re-runs don't insert twice
+    fn insert(text: &str, at: usize, content: &str, run: &[String]) -> Option<String> {
+        if Self::already_at(text, at, &Self::as_lines(content), run) {
+            return None;
+        }
+
+        let mut patched = text[..at].to_string();
+        if !patched.is_empty() && !patched.ends_with('\n') {
+            patched.push('\n');
+        }
+        patched.push_str(&Self::as_lines(content));
+        patched.push_str(&text[at..]);
+        Some(patched)
+    }
+
+    /// Whether `block` (whole lines) is among the blocks from `run` stacked up on either side
+    /// of the line boundary `at`
+    fn already_at(text: &str, at: usize, block: &str, run: &[String]) -> bool {
+        if block.is_empty() {
+            return true;
+        }
+
+        // a last line without its newline still counts
+        let mut before = text[..at].to_string();
+        if at == text.len() && !before.is_empty() && !before.ends_with('\n') {
+            before.push('\n');
+        }
+        let mut after = text[at..].to_string();
+        if !after.is_empty() && !after.ends_with('\n') {
+            after.push('\n');
+        }
+
+        let mut rest = after.as_str();
+        loop {
+            if rest.starts_with(block) {
+                return true;
+            }
+            match run.iter().find(|b| rest.starts_with(b.as_str())) {
+                Some(b) => rest = &rest[b.len()..],
+                None => break,
+            }
+        }
+
+        let ends_with = |text: &str, block: &str| {
+            text.ends_with(block)
+                && text[..text.len() - block.len()]
+                    .chars()
+                    .last()
+                    .is_none_or(|c| c == '\n')
+        };
+        let mut rest = before.as_str();
+        loop {
+            if ends_with(rest, block) {
+                return true;
+            }
+            match run.iter().find(|b| ends_with(rest, b)) {
+                Some(b) => rest = &rest[..rest.len() - b.len()],
+                None => return false,
+            }
+        }
+    }
+
+    fn as_lines(content: &str) -> String {
+        if content.is_empty() || content.ends_with('\n') {
+            return content.to_string();
+        }
+        format!("{}\n", content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn patch(dir: &TempDir, text: &str, action: PatchAction) -> (bool, String) {
+        let path = dir.path().join("file");
+        fs::write(&path, text).unwrap();
+        let item = PatchItem {
+            feedback: None,
+            file: path.display().to_string(),
+            action,
+        };
+        let changed = Patches::apply(&item, &[]).unwrap();
+        (changed, fs::read_to_string(&path).unwrap())
+    }
+
+    /// Applies `action` twice, the second run must leave the file alone
+    fn patch_twice(text: &str, action: PatchAction) -> String {
+        let dir = TempDir::new().unwrap();
+        let (changed, once) = patch(&dir, text, action.clone());
+        assert!(changed, "first run should patch");
+        let (changed, twice) = patch(&dir, &once, action);
+        assert!(!changed, "second run should be a no-op");
+        assert_eq!(once, twice);
+        once
+    }
+
+    /// Applies `actions` in order like one step would, then again, the second run must
+    /// leave the file alone
+    fn patch_all_twice(text: &str, actions: Vec<PatchAction>) -> String {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("file");
+        fs::write(&path, text).unwrap();
+        let patches: Vec<PatchItem> = actions
+            .into_iter()
+            .map(|action| PatchItem {
+                feedback: None,
+                file: path.display().to_string(),
+                action,
+            })
+            .collect();
+
+        let mut runs = vec![];
+        for _ in 0..2 {
+            for patch in patches.iter() {
+                Patches::apply(patch, &Patches::run(&patches, patch)).unwrap();
+            }
+            runs.push(fs::read_to_string(&path).unwrap());
+        }
+        assert_eq!(runs[0], runs[1], "second run should be a no-op");
+        runs.remove(0)
+    }
+
+    fn append(content: &str) -> PatchAction {
+        PatchAction::Append {
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn append_is_idempotent() {
+        assert_eq!(patch_twice("A=1\n", append("B=2")), "A=1\nB=2\n");
+    }
+
+    #[test]
+    fn append_without_trailing_newline() {
+        assert_eq!(patch_twice("A=1", append("B=2")), "A=1\nB=2\n");
+    }
+
+    #[test]
+    fn append_near_match_is_not_already_patched() {
+        assert_eq!(patch_twice("MAX=10\n", append("X=1")), "MAX=10\nX=1\n");
+    }
+
+    #[test]
+    fn append_content_elsewhere_in_file_still_appends() {
+        assert_eq!(patch_twice("X=1\nY=2\n", append("X=1")), "X=1\nY=2\nX=1\n");
+    }
+
+    #[test]
+    fn appends_to_the_same_file_are_idempotent() {
+        assert_eq!(
+            patch_all_twice("X=0\n", vec![append("A=1"), append("B=2")]),
+            "X=0\nA=1\nB=2\n"
+        );
+    }
+
+    #[test]
+    fn prepends_to_the_same_file_are_idempotent() {
+        let prepend = |content: &str| PatchAction::Prepend {
+            content: content.to_string(),
+        };
+        assert_eq!(
+            patch_all_twice("code\n", vec![prepend("// a"), prepend("// b")]),
+            "// b\n// a\ncode\n"
+        );
+    }
+
+    #[test]
+    fn prepend_is_idempotent() {
+        let action = PatchAction::Prepend {
+            content: "// header".to_string(),
+        };
+        assert_eq!(patch_twice("code\n", action), "// header\ncode\n");
+    }
+
+    #[test]
+    fn insert_after_is_idempotent() {
+        let action = PatchAction::InsertAfter {
+            pattern: "^import a$".to_string(),
+            content: "import b".to_string(),
+        };
+        assert_eq!(
+            patch_twice("import a\nrest\n", action),
+            "import a\nimport b\nrest\n"
+        );
+    }
+
+    #[test]
+    fn insert_after_near_match() {
+        let action = PatchAction::InsertAfter {
+            pattern: "^import a$".to_string(),
+            content: "import b".to_string(),
+        };
+        assert_eq!(
+            patch_twice("import a\nimport bc\n", action),
+            "import a\nimport b\nimport bc\n"
+        );
+    }
+
+    #[test]
+    fn inserts_after_the_same_anchor_are_idempotent() {
+        let insert_after = |content: &str| PatchAction::InsertAfter {
+            pattern: "^import a$".to_string(),
+            content: content.to_string(),
+        };
+        assert_eq!(
+            patch_all_twice(
+                "import a\nrest\n",
+                vec![insert_after("import b"), insert_after("import c")]
+            ),
+            "import a\nimport c\nimport b\nrest\n"
+        );
+    }
+
+    #[test]
+    fn inserts_before_the_same_anchor_are_idempotent() {
+        let insert_before = |content: &str| PatchAction::InsertBefore {
+            pattern: "^export".to_string(),
+            content: content.to_string(),
+        };
+        assert_eq!(
+            patch_all_twice(
+                "export a\n",
+                vec![insert_before("import x"), insert_before("import y")]
+            ),
+            "import x\nimport y\nexport a\n"
+        );
+    }
+
+    #[test]
+    fn insert_before_is_idempotent() {
+        let action = PatchAction::InsertBefore {
+            pattern: "^export".to_string(),
+            content: "import x".to_string(),
+        };
+        assert_eq!(
+            patch_twice("const a = 1\nexport a\n", action),
+            "const a = 1\nimport x\nexport a\n"
+        );
+    }
+
+    #[test]
+    fn insert_before_near_match() {
+        let action = PatchAction::InsertBefore {
+            pattern: "^export".to_string(),
+            content: "x".to_string(),
+        };
+        assert_eq!(patch_twice("xx\nexport a\n", action), "xx\nx\nexport a\n");
+    }
+
+    #[test]
+    fn replace_between_is_idempotent() {
+        let action = PatchAction::ReplaceBetween {
+            start: "^# start$".to_string(),
+            end: "^# end$".to_string(),
+            content: "new".to_string(),
+        };
+        assert_eq!(
+            patch_twice("a\n# start\nold\nlines\n# end\nb\n", action),
+            "a\n# start\nnew\n# end\nb\n"
+        );
+    }
+
+    #[test]
+    fn missing_pattern_fails() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("file");
+        fs::write(&path, "a\n").unwrap();
+        let item = PatchItem {
+            feedback: None,
+            file: path.display().to_string(),
+            action: PatchAction::InsertAfter {
+                pattern: "^nope$".to_string(),
+                content: "b".to_string(),
+            },
+        };
+        assert!(Patches::apply(&item, &[]).is_err());
+    }
+}
@@ -0,0 +1,60 @@
+use crate::manifest::{BuildItem, Manifest, PatchAction};
+use crate::wait_for::WaitFor;
+
+pub struct Plan;
+
+impl Plan {
+    /// What each step would do, in the order a build does it, without running anything
+    pub fn lines(manifest: &Manifest, only: &[String]) -> Vec<String> {
+        let mut lines = vec![format!(
+            "📋 Plan for {} ({})",
+            manifest.app_name, manifest.source
+        )];
+        for item in manifest.json.builder.iter() {
+            if !(only.is_empty() || only.contains(&item.tag)) {
+                lines.push(format!("--- [{}] skipped", item.tag));
+                continue;
+            }
+            lines.push(format!("==> [{}] {}", item.tag, item.feedback));
+            lines.extend(Self::step(item).iter().map(|line| format!("    {}", line)));
+        }
+        lines
+    }
+
+    fn step(item: &BuildItem) -> Vec<String> {
+        let mut lines = vec![];
+        if let Some(requires) = &item.requires {
+            lines.push(format!("requires {}", requires.join(", ")));
+        }
+        for check in item.checks.iter().flatten() {
+            lines.push(format!("check {}", check.label()));
+        }
+        if let Some(services) = &item.services {
+            lines.push(format!("start {}", services.join(", ")));
+        }
+        for wait in item.wait_for.iter().flatten() {
+            lines.push(format!("wait for {}", WaitFor::describe(&wait.probe)));
+        }
+        for command in item.commands.iter().flatten() {
+            lines.push(format!(
+                "$ {} (in {})",
+                command.command,
+                command.context.as_deref().unwrap_or(".")
+            ));
+        }
+        for template in item.templates.iter().flatten() {
+            lines.push(format!("copy {} to {}", template.source, template.dest));
+        }
+        for patch in item.patches.iter().flatten() {
+            let action = match patch.action {
+                PatchAction::InsertAfter { .. } => "insert after",
+                PatchAction::InsertBefore { .. } => "insert before",
+                PatchAction::ReplaceBetween { .. } => "replace between",
+                PatchAction::Append { .. } => "append",
+                PatchAction::Prepend { .. } => "prepend",
+            };
+            lines.push(format!("patch {} ({})", patch.file, action));
+        }
+        lines
+    }
+}
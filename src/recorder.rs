@@ -0,0 +1,146 @@
+use crate::reporter::{BuildSummary, Event, Reporter};
+use serde::Serialize;
+use std::{cell::RefCell, time::Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Finished,
+    Skipped,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StepRecord {
+    pub tag: String,
+    pub status: StepStatus,
+    pub duration_ms: Option<u64>,
+    pub commands: Vec<CommandRecord>,
+    pub templates: Vec<TemplateRecord>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandRecord {
+    pub command: String,
+    pub cwd: String,
+    pub code: Option<i32>,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TemplateRecord {
+    pub source: String,
+    pub dest: String,
+    pub duration_ms: u64,
+}
+
+/// Passes events through to another reporter while keeping a record of the build
+pub struct Recorder<'a> {
+    inner: &'a dyn Reporter,
+    started: Instant,
+    summary: RefCell<BuildSummary>,
+    steps: RefCell<Vec<StepRecord>>,
+}
+
+impl<'a> Recorder<'a> {
+    pub fn new(inner: &'a dyn Reporter) -> Recorder<'a> {
+        Recorder {
+            inner,
+            started: Instant::now(),
+            summary: RefCell::new(BuildSummary::default()),
+            steps: RefCell::new(vec![]),
+        }
+    }
+
+    pub fn summary(&self) -> BuildSummary {
+        let mut summary = self.summary.borrow().clone();
+        summary.duration_ms = self.started.elapsed().as_millis() as u64;
+        summary
+    }
+
+    /// Every step in manifest order, one still marked failed never finished
+    pub fn steps(&self) -> Vec<StepRecord> {
+        self.steps.borrow().clone()
+    }
+
+    fn record(&self, event: &Event) {
+        let mut summary = self.summary.borrow_mut();
+        let mut steps = self.steps.borrow_mut();
+        match event {
+            Event::StepStarted { tag, .. } => steps.push(StepRecord {
+                tag: tag.to_owned(),
+                status: StepStatus::Failed,
+                duration_ms: None,
+                commands: vec![],
+                templates: vec![],
+            }),
+            Event::StepFinished { duration_ms, .. } => {
+                summary.steps_run += 1;
+                if let Some(step) = steps.last_mut() {
+                    step.status = StepStatus::Finished;
+                    step.duration_ms = Some(*duration_ms);
+                }
+            }
+            Event::StepSkipped { tag } => {
+                summary.steps_skipped += 1;
+                steps.push(StepRecord {
+                    tag: tag.to_owned(),
+                    status: StepStatus::Skipped,
+                    duration_ms: None,
+                    commands: vec![],
+                    templates: vec![],
+                });
+            }
+            Event::CommandExited {
+                argv,
+                cwd,
+                code,
+                duration_ms,
+            } => {
+                summary.commands += 1;
+                if *code != Some(0) {
+                    summary.commands_failed += 1;
+                }
+                if let Some(step) = steps.last_mut() {
+                    step.commands.push(CommandRecord {
+                        command: argv.join(" "),
+                        cwd: cwd.to_owned(),
+                        code: *code,
+                        duration_ms: *duration_ms,
+                    });
+                }
+            }
+            Event::CommandRetrying { .. } => summary.retries += 1,
+            Event::TemplateFinished {
+                source,
+                dest,
+                duration_ms,
+            } => {
+                if let Some(step) = steps.last_mut() {
+                    step.templates.push(TemplateRecord {
+                        source: source.to_owned(),
+                        dest: dest.to_owned(),
+                        duration_ms: *duration_ms,
+                    });
+                }
+            }
+            Event::FileWritten { outcome, .. } => *summary.files.entry(*outcome).or_default() += 1,
+            _ => {}
+        }
+    }
+}
+
+impl Reporter for Recorder<'_> {
+    fn report(&self, event: &Event) {
+        self.record(event);
+        self.inner.report(event);
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        self.inner.suspend(f);
+    }
+
+    fn passes_output_through(&self) -> bool {
+        self.inner.passes_output_through()
+    }
+}
@@ -0,0 +1,193 @@
+use crate::manifest::Manifest;
+use crate::recorder::{StepRecord, StepStatus};
+use crate::reporter::BuildSummary;
+use regex::Regex;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const REPORT_DIR: &str = ".lumberstack";
+
+// variable names that look like they hold credentials are kept out of the report
+const SECRET_PATTERN: &str = r"(?i)secret|passw(or)?d|token|api_?key|private|credential";
+const REDACTED: &str = "[redacted]";
+
+/// What `Lumberstack::run` leaves in `<app>/.lumberstack/report.json`
+#[derive(Debug, Serialize)]
+pub struct BuildReport {
+    pub lumberstack_version: String,
+    /// Seconds since the unix epoch
+    pub generated_at: u64,
+    pub success: bool,
+    pub error: Option<String>,
+    pub manifest: ManifestRecord,
+    pub variables: BTreeMap<String, String>,
+    pub templates: Vec<TemplateRecord>,
+    pub steps: Vec<StepRecord>,
+    pub summary: BuildSummary,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ManifestRecord {
+    pub source: String,
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TemplateRecord {
+    pub source: String,
+    /// Commit the template source was at, when it lives in a git checkout
+    pub git_ref: Option<String>,
+}
+
+impl BuildReport {
+    pub fn new(
+        manifest: &Manifest,
+        steps: Vec<StepRecord>,
+        summary: BuildSummary,
+        error: Option<String>,
+    ) -> BuildReport {
+        let ran: Vec<&str> = steps
+            .iter()
+            .filter(|s| s.status != StepStatus::Skipped)
+            .map(|s| s.tag.as_str())
+            .collect();
+        let template_items = manifest
+            .json
+            .builder
+            .iter()
+            .filter(|item| ran.contains(&item.tag.as_str()))
+            .flat_map(|item| item.templates.iter().flatten());
+
+        let secret = Regex::new(SECRET_PATTERN).unwrap();
+        let mut variables = BTreeMap::new();
+        variables.insert("app_name".to_string(), manifest.app_name.to_owned());
+        let mut templates: Vec<TemplateRecord> = vec![];
+
+        for template_item in template_items {
+            for (key, value) in template_item.replace_map.iter().flatten() {
+                let value = match secret.is_match(key) {
+                    true => REDACTED.to_string(),
+                    false => value.to_owned(),
+                };
+                variables.insert(key.to_owned(), value);
+            }
+
+            if !templates.iter().any(|t| t.source == template_item.source) {
+                templates.push(TemplateRecord {
+                    source: template_item.source.to_owned(),
+                    git_ref: Self::git_ref(Path::new(&template_item.source)),
+                });
+            }
+        }
+
+        BuildReport {
+            lumberstack_version: env!("CARGO_PKG_VERSION").to_string(),
+            generated_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            success: error.is_none(),
+            error,
+            manifest: ManifestRecord {
+                source: manifest.source.to_owned(),
+                sha256: manifest.sha256.to_owned(),
+            },
+            variables,
+            templates,
+            steps,
+            summary,
+        }
+    }
+
+    /// Writes `report.json` (and `report.md`) under `<app_dir>/.lumberstack`, returns the json path
+    pub fn write(&self, app_dir: &Path, markdown: bool) -> io::Result<PathBuf> {
+        let dir = app_dir.join(REPORT_DIR);
+        fs::create_dir_all(&dir)?;
+
+        let path = dir.join("report.json");
+        let mut json = serde_json::to_string_pretty(self)?;
+        json.push('\n');
+        fs::write(&path, json)?;
+
+        if markdown {
+            fs::write(dir.join("report.md"), self.to_markdown())?;
+        }
+        Ok(path)
+    }
+
+    fn git_ref(source: &Path) -> Option<String> {
+        let dir = match source.is_dir() {
+            true => source,
+            false => source.parent().filter(|p| !p.as_os_str().is_empty())?,
+        };
+        let output = Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .current_dir(dir)
+            .output()
+            .ok()?;
+
+        match output.status.success() {
+            true => Some(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+            false => None,
+        }
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut md = String::from("# Lumberstack build report\n\n");
+        md.push_str(&format!("- Lumberstack: {}\n", self.lumberstack_version));
+        md.push_str(&format!("- Generated at: {} (unix)\n", self.generated_at));
+        md.push_str(&format!(
+            "- Manifest: {} (sha256 `{}`)\n",
+            self.manifest.source, self.manifest.sha256
+        ));
+        match &self.error {
+            None => md.push_str("- Result: success\n"),
+            Some(error) => md.push_str(&format!("- Result: failed - {}\n", error)),
+        }
+
+        md.push_str("\n## Variables\n\n| Name | Value |\n| ---- | ----- |\n");
+        for (key, value) in self.variables.iter() {
+            md.push_str(&format!("| {} | {} |\n", key, value));
+        }
+
+        if !self.templates.is_empty() {
+            md.push_str("\n## Templates\n\n| Source | Ref |\n| ------ | --- |\n");
+            for template in self.templates.iter() {
+                let git_ref = template.git_ref.as_deref().unwrap_or("-");
+                md.push_str(&format!("| {} | {} |\n", template.source, git_ref));
+            }
+        }
+
+        md.push_str("\n## Steps\n\n| Step | Status | Duration | Commands |\n| ---- | ------ | -------- | -------- |\n");
+        for step in self.steps.iter() {
+            let status = serde_json::to_value(step.status)
+                .ok()
+                .and_then(|v| v.as_str().map(String::from))
+                .unwrap_or_default();
+            let duration = step
+                .duration_ms
+                .map_or("-".to_string(), |ms| format!("{:.1}s", ms as f64 / 1000.0));
+            let commands: Vec<String> = step
+                .commands
+                .iter()
+                .map(|c| match c.code {
+                    Some(code) => format!("`{}` ({})", c.command, code),
+                    None => format!("`{}` (signal)", c.command),
+                })
+                .collect();
+            md.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                step.tag,
+                status,
+                duration,
+                commands.join("<br>")
+            ));
+        }
+        md
+    }
+}
@@ -0,0 +1,405 @@
+use crate::conflicts::{FileOutcome, TemplateSummary};
+use crate::requirements::{RequirementRow, Requirements};
+use crate::spinner::create_spinner;
+use crate::timings::{TimingRow, Timings};
+use indicatif::ProgressBar;
+use log::warn;
+use serde::Serialize;
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt::Display,
+    io::{self, IsTerminal, Write},
+    path::Path,
+};
+
+// `println!` panics once stdout is a closed pipe, which is normal for `--output json | head`
+macro_rules! out {
+    ($($arg:tt)*) => {
+        print_line(format_args!($($arg)*))
+    };
+}
+
+/// Writes a line to stdout, quietly dropping it when whoever was reading has gone away
+pub fn print_line(line: impl Display) {
+    let mut stdout = io::stdout().lock();
+    if let Err(e) = writeln!(stdout, "{}", line) {
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            warn!("Could not write to stdout - {}", e);
+        }
+    }
+}
+
+/// Something that happened during a build
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    ChecksStarted,
+    /// Installed tool versions against the manifest's `requirements`
+    RequirementsChecked {
+        rows: Vec<RequirementRow>,
+    },
+    StepStarted {
+        tag: String,
+        feedback: String,
+    },
+    StepFinished {
+        tag: String,
+        duration_ms: u64,
+    },
+    /// Left out by `--only`
+    StepSkipped {
+        tag: String,
+    },
+    /// A command, template or patch's own `feedback`
+    Feedback {
+        message: String,
+    },
+    /// Polling a `wait_for` probe until it passes or `timeout_ms` runs out
+    WaitStarted {
+        probe: String,
+        timeout_ms: u64,
+    },
+    WaitFinished {
+        probe: String,
+        duration_ms: u64,
+    },
+    CommandStarted {
+        argv: Vec<String>,
+        cwd: String,
+    },
+    CommandOutput {
+        line: String,
+    },
+    CommandExited {
+        argv: Vec<String>,
+        cwd: String,
+        /// `None` when the command was killed by a signal
+        code: Option<i32>,
+        duration_ms: u64,
+    },
+    /// A command failed for good, `tail` is the end of its output from `log`
+    CommandFailed {
+        argv: Vec<String>,
+        log: String,
+        tail: Vec<String>,
+    },
+    /// A failed attempt that will be tried again after `delay_ms`
+    CommandRetrying {
+        argv: Vec<String>,
+        attempt: u32,
+        attempts: u32,
+        delay_ms: u64,
+        error: String,
+    },
+    TemplateStarted {
+        source: String,
+        dest: String,
+    },
+    TemplateFinished {
+        source: String,
+        dest: String,
+        duration_ms: u64,
+    },
+    FileWritten {
+        path: String,
+        outcome: FileOutcome,
+        /// sha256 of what ended up on disk, `None` for symlinks
+        hash: Option<String>,
+    },
+    PatchApplied {
+        file: String,
+        changed: bool,
+    },
+    /// Every step, command and template timing of the build, slowest first
+    Timings {
+        rows: Vec<TimingRow>,
+    },
+    /// Where `.lumberstack/report.json` (and the markdown version) ended up
+    ReportWritten {
+        path: String,
+    },
+    BuildFinished {
+        summary: BuildSummary,
+    },
+    BuildFailed {
+        error: String,
+        hint: Option<String>,
+        summary: BuildSummary,
+    },
+}
+
+impl Event {
+    pub fn file_written(path: &Path, outcome: FileOutcome, hash: Option<String>) -> Event {
+        Event::FileWritten {
+            path: path.display().to_string(),
+            outcome,
+            hash,
+        }
+    }
+}
+
+/// Totals for a whole build, sent with `BuildFinished` and `BuildFailed`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BuildSummary {
+    pub steps_run: usize,
+    pub steps_skipped: usize,
+    pub commands: usize,
+    pub commands_failed: usize,
+    /// Failed attempts that were tried again
+    pub retries: usize,
+    pub files: BTreeMap<FileOutcome, usize>,
+    pub duration_ms: u64,
+}
+
+/// Where a build reports its progress. The CLI picks one with `--output`, embedders can bring their own.
+pub trait Reporter {
+    fn report(&self, event: &Event);
+    /// Runs `f` with the progress display out of the way, for prompts and printed output
+    fn suspend(&self, f: &mut dyn FnMut());
+    /// Whether commands write straight to the terminal instead of through `CommandOutput`
+    /// events, so they keep their colours and prompts without a newline still show
+    fn passes_output_through(&self) -> bool {
+        false
+    }
+}
+
+/// With -vv on a terminal command output is shown as the command writes it
+fn terminal_output() -> bool {
+    log::log_enabled!(log::Level::Info) && io::stdout().is_terminal()
+}
+
+/// Ignores everything, the default when embedding
+pub struct SilentReporter;
+
+impl Reporter for SilentReporter {
+    fn report(&self, _event: &Event) {}
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        f();
+    }
+}
+
+/// The interactive spinner
+pub struct SpinnerReporter {
+    spinner: ProgressBar,
+    summary: RefCell<TemplateSummary>,
+}
+
+impl SpinnerReporter {
+    pub fn new() -> SpinnerReporter {
+        SpinnerReporter {
+            spinner: create_spinner(),
+            summary: RefCell::new(TemplateSummary::default()),
+        }
+    }
+}
+
+impl Default for SpinnerReporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reporter for SpinnerReporter {
+    fn report(&self, event: &Event) {
+        let spinner = &self.spinner;
+        match event {
+            Event::ChecksStarted => {
+                spinner.set_prefix("🚀");
+                spinner.set_message("Checking system requirements");
+            }
+            Event::RequirementsChecked { rows } => {
+                // only worth the space when something is off
+                if rows.iter().any(|row| !row.ok) {
+                    let lines = Requirements::lines(rows);
+                    spinner.suspend(|| lines.iter().for_each(|line| out!("{}", line)));
+                }
+            }
+            Event::StepStarted { feedback, .. } => spinner.set_message(feedback.to_owned()),
+            Event::Feedback { message } => spinner.set_message(message.to_owned()),
+            Event::WaitStarted { probe, .. } => {
+                spinner.set_prefix("⏳");
+                spinner.set_message(format!("Waiting for {}", probe));
+            }
+            Event::CommandStarted { .. } => spinner.set_prefix("👟"),
+            Event::CommandOutput { line } => {
+                if log::log_enabled!(log::Level::Info) {
+                    spinner.suspend(|| out!("{}", line));
+                }
+            }
+            Event::CommandFailed { log, tail, .. } => {
+                let lines = failure_lines(log, tail);
+                spinner.suspend(|| lines.iter().for_each(|line| out!("{}", line)));
+            }
+            Event::CommandRetrying {
+                argv,
+                attempt,
+                attempts,
+                delay_ms,
+                ..
+            } => spinner.set_message(format!(
+                "Retrying {} ({}/{}) in {:.0}s",
+                argv.join(" "),
+                attempt + 1,
+                attempts,
+                *delay_ms as f64 / 1000.0
+            )),
+            Event::TemplateStarted { .. } => spinner.set_prefix("📄"),
+            Event::FileWritten { path, outcome, .. } => {
+                self.summary.borrow_mut().record(Path::new(path), *outcome)
+            }
+            Event::PatchApplied { .. } => spinner.set_prefix("🩹"),
+            Event::BuildFinished { .. } => {
+                // suspend rather than println so the summary also shows when the spinner is hidden
+                let lines = self.summary.borrow().lines();
+                spinner.suspend(|| lines.iter().for_each(|line| out!("{}", line)));
+                spinner.set_prefix("✅");
+                spinner.finish_with_message("Lumberstack Complete!");
+            }
+            Event::Timings { rows } => {
+                let lines = Timings::lines(rows);
+                spinner.suspend(|| lines.iter().for_each(|line| out!("{}", line)));
+            }
+            Event::BuildFailed { .. } => spinner.finish_and_clear(),
+            Event::StepFinished { .. }
+            | Event::StepSkipped { .. }
+            | Event::CommandExited { .. }
+            | Event::WaitFinished { .. }
+            | Event::TemplateFinished { .. }
+            | Event::ReportWritten { .. } => {}
+        }
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        self.spinner.suspend(f);
+    }
+
+    fn passes_output_through(&self) -> bool {
+        terminal_output()
+    }
+}
+
+/// One line per event, for CI logs
+#[derive(Default)]
+pub struct PlainReporter {
+    summary: RefCell<TemplateSummary>,
+}
+
+impl Reporter for PlainReporter {
+    fn report(&self, event: &Event) {
+        match event {
+            Event::ChecksStarted => out!("Checking system requirements"),
+            Event::RequirementsChecked { rows } => Requirements::lines(rows)
+                .iter()
+                .for_each(|line| out!("{}", line)),
+            Event::StepStarted { tag, feedback } => out!("==> [{}] {}", tag, feedback),
+            Event::StepSkipped { tag } => out!("==> [{}] skipped", tag),
+            Event::Feedback { message } => out!("  {}", message),
+            Event::WaitStarted { probe, timeout_ms } => {
+                out!("  waiting for {} (up to {}s)", probe, timeout_ms / 1000)
+            }
+            Event::WaitFinished { probe, duration_ms } => out!(
+                "  {} ready after {:.1}s",
+                probe,
+                *duration_ms as f64 / 1000.0
+            ),
+            Event::CommandStarted { argv, cwd } => out!("  $ {} (in {})", argv.join(" "), cwd),
+            Event::CommandOutput { line } => {
+                if log::log_enabled!(log::Level::Info) {
+                    out!("    {}", line);
+                }
+            }
+            Event::CommandExited { code, .. } => match code {
+                Some(0) => {}
+                Some(code) => out!("  exited with code {}", code),
+                None => out!("  terminated by signal"),
+            },
+            Event::CommandFailed { log, tail, .. } => failure_lines(log, tail)
+                .iter()
+                .for_each(|line| out!("{}", line)),
+            Event::CommandRetrying {
+                attempt,
+                attempts,
+                delay_ms,
+                error,
+                ..
+            } => out!(
+                "  attempt {}/{} failed ({}), retrying in {:.0}s",
+                attempt,
+                attempts,
+                error,
+                *delay_ms as f64 / 1000.0
+            ),
+            Event::TemplateStarted { source, dest } => out!("  copying {} to {}", source, dest),
+            Event::FileWritten { path, outcome, .. } => {
+                self.summary.borrow_mut().record(Path::new(path), *outcome)
+            }
+            Event::PatchApplied { file, changed } => match changed {
+                true => out!("  patched {}", file),
+                false => out!("  {} already patched", file),
+            },
+            Event::Timings { rows } => Timings::lines(rows)
+                .iter()
+                .for_each(|line| out!("{}", line)),
+            Event::ReportWritten { path } => out!("Build report written to {}", path),
+            Event::BuildFinished { summary } => {
+                self.summary
+                    .borrow()
+                    .lines()
+                    .iter()
+                    .for_each(|line| out!("{}", line));
+                let retries = match summary.retries {
+                    0 => String::new(),
+                    n => format!(" ({} retried)", n),
+                };
+                out!(
+                    "Lumberstack Complete! {} steps, {} commands{} in {:.1}s",
+                    summary.steps_run,
+                    summary.commands,
+                    retries,
+                    summary.duration_ms as f64 / 1000.0
+                );
+            }
+            // the error itself is logged by whoever ran the build
+            Event::StepFinished { .. }
+            | Event::TemplateFinished { .. }
+            | Event::BuildFailed { .. } => {}
+        }
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        f();
+    }
+
+    fn passes_output_through(&self) -> bool {
+        terminal_output()
+    }
+}
+
+/// The end of a failed command's output, unless it was already shown with -vv
+fn failure_lines(log: &str, tail: &[String]) -> Vec<String> {
+    let mut lines = vec![];
+    if !log::log_enabled!(log::Level::Info) && !tail.is_empty() {
+        lines.push(format!("--- last {} lines of output ---", tail.len()));
+        lines.extend(tail.iter().cloned());
+        lines.push("---".to_string());
+    }
+    lines.push(format!("📜 Full output in {}", log));
+    lines
+}
+
+/// Newline delimited json, one object per event
+pub struct JsonReporter;
+
+impl Reporter for JsonReporter {
+    fn report(&self, event: &Event) {
+        if let Ok(json) = serde_json::to_string(event) {
+            out!("{}", json);
+        }
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        f();
+    }
+}
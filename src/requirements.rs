@@ -0,0 +1,191 @@
+use crate::error::{LumberstackError, Result};
+use log::debug;
+use regex::Regex;
+use semver::{Version, VersionReq};
+use serde::Serialize;
+use std::{collections::BTreeMap, process::Command};
+
+/// One tool's installed version against the manifest's range
+#[derive(Debug, Clone, Serialize)]
+pub struct RequirementRow {
+    pub tool: String,
+    pub required: String,
+    /// `None` when the tool isn't installed or printed no version
+    pub found: Option<String>,
+    pub ok: bool,
+}
+
+/// An npm style range, any of its `||` alternatives can match
+#[derive(Debug, Clone)]
+pub struct VersionRange(Vec<VersionReq>);
+
+impl VersionRange {
+    pub fn matches(&self, version: &Version) -> bool {
+        self.0.iter().any(|req| req.matches(version))
+    }
+}
+
+pub struct Requirements;
+
+impl Requirements {
+    /// Parses an npm style range, `>=18 <21` as well as `>=18, <21` and `^18 || ^20`
+    pub fn parse(range: &str) -> std::result::Result<VersionRange, semver::Error> {
+        let operator_gap = Regex::new(r"([<>=~^]+)\s+").unwrap();
+        range
+            .split("||")
+            .map(|alternative| {
+                let alternative = operator_gap.replace_all(alternative.trim(), "$1");
+                let alternative = match alternative.contains(',') {
+                    true => alternative.to_string(),
+                    false => alternative
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                };
+                VersionReq::parse(&alternative)
+            })
+            .collect::<std::result::Result<_, _>>()
+            .map(VersionRange)
+    }
+
+    /// Checks every tool in `requirements`, all of them run so the table is complete
+    pub fn check(requirements: &BTreeMap<String, String>) -> Vec<RequirementRow> {
+        requirements
+            .iter()
+            .map(|(tool, required)| {
+                let found = Self::installed_version(tool);
+                let ok = match (&found, Self::parse(required)) {
+                    (Some(version), Ok(range)) => range.matches(version),
+                    _ => false,
+                };
+                RequirementRow {
+                    tool: tool.to_owned(),
+                    required: required.to_owned(),
+                    found: found.map(|v| v.to_string()),
+                    ok,
+                }
+            })
+            .collect()
+    }
+
+    /// The first failed row as an error
+    pub fn verify(rows: &[RequirementRow]) -> Result<()> {
+        match rows.iter().find(|row| !row.ok) {
+            Some(row) => Err(LumberstackError::Requirement {
+                tool: row.tool.to_owned(),
+                found: row.found.to_owned().unwrap_or("nothing".to_string()),
+                required: row.required.to_owned(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// `<tool> --version`, reading the first thing that looks like a version
+    pub(crate) fn installed_version(tool: &str) -> Option<Version> {
+        let output = Command::new(tool).arg("--version").output().ok()?;
+        let text = format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        debug!("{} --version: {}", tool, text.trim());
+        Self::first_version(&text)
+    }
+
+    /// The first thing in `text` that looks like a version, missing parts are 0
+    fn first_version(text: &str) -> Option<Version> {
+        let version = Regex::new(r"(\d+)(?:\.(\d+))?(?:\.(\d+))?").unwrap();
+        let captures = version.captures(text)?;
+        let part = |i: usize| {
+            captures
+                .get(i)
+                .and_then(|m| m.as_str().parse().ok())
+                .unwrap_or(0)
+        };
+        Some(Version::new(part(1), part(2), part(3)))
+    }
+
+    pub fn lines(rows: &[RequirementRow]) -> Vec<String> {
+        if rows.is_empty() {
+            return vec![];
+        }
+
+        let mut lines = vec!["🔧 Requirements:".to_string()];
+        lines.push(format!("   {:<10}{:<12}required", "tool", "found"));
+        for row in rows.iter() {
+            let flag = if row.ok { "✅" } else { "❌" };
+            lines.push(format!(
+                "{} {:<10}{:<12}{}",
+                flag,
+                row.tool,
+                row.found.as_deref().unwrap_or("-"),
+                row.required
+            ));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(range: &str, version: &str) -> bool {
+        Requirements::parse(range)
+            .unwrap()
+            .matches(&Version::parse(version).unwrap())
+    }
+
+    #[test]
+    fn space_separated_comparators_all_apply() {
+        assert!(matches(">=18 <21", "20.1.0"));
+        assert!(!matches(">=18 <21", "21.0.0"));
+        assert!(!matches(">=18 <21", "16.0.0"));
+    }
+
+    #[test]
+    fn comma_separated_comparators_all_apply() {
+        assert!(matches(">=18, <21", "18.0.0"));
+        assert!(!matches(">=18, <21", "21.0.0"));
+    }
+
+    #[test]
+    fn space_after_operator() {
+        assert!(matches(">= 18 < 21", "19.0.0"));
+        assert!(!matches(">= 18 < 21", "17.9.0"));
+    }
+
+    #[test]
+    fn bare_version_is_caret() {
+        assert!(matches("1.22", "1.22.19"));
+        assert!(!matches("1.22", "2.0.0"));
+        assert!(!matches("1.22", "1.21.0"));
+    }
+
+    #[test]
+    fn any_alternative_can_match() {
+        assert!(matches("^18 || ^20", "18.2.0"));
+        assert!(matches("^18 || ^20", "20.11.0"));
+        assert!(!matches("^18 || ^20", "19.0.0"));
+        assert!(matches(">=14 <15 || >= 20", "22.0.0"));
+    }
+
+    #[test]
+    fn invalid_ranges_fail() {
+        assert!(Requirements::parse("^18 ||").is_err());
+        assert!(Requirements::parse("latest").is_err());
+    }
+
+    #[test]
+    fn versions_from_version_output() {
+        let version = |text: &str| Requirements::first_version(text).map(|v| v.to_string());
+        assert_eq!(version("v20.1.0\n"), Some("20.1.0".to_string()));
+        assert_eq!(
+            version("Docker version 24.0.5, build ced0996\n"),
+            Some("24.0.5".to_string())
+        );
+        assert_eq!(version("1.22\n"), Some("1.22.0".to_string()));
+        assert_eq!(version("podman version 4\n"), Some("4.0.0".to_string()));
+        assert_eq!(version("command not found\n"), None);
+    }
+}
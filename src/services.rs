@@ -0,0 +1,270 @@
+use crate::commands::Commands;
+use crate::container::ContainerRuntime;
+use crate::error::{LumberstackError, Result};
+use crate::manifest::ServiceItem;
+use crate::report::REPORT_DIR;
+use crate::reporter::{Event, Reporter, SilentReporter};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    net::TcpListener,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const STATE_FILE: &str = "services.json";
+const DEFAULT_COMPOSE_FILE: &str = "docker-compose.yml";
+
+/// What lumberstack created, kept in `<app>/.lumberstack/services.json` so `clean`
+/// removes exactly that
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ServicesState {
+    pub project_dir: String,
+    /// Cleaning has to go through the runtime that created everything
+    #[serde(default)]
+    pub runtime: ContainerRuntime,
+    pub services: Vec<StartedService>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StartedService {
+    pub name: String,
+    pub context: String,
+    pub compose_file: String,
+    pub containers: Vec<String>,
+    pub volumes: Vec<String>,
+}
+
+impl ServicesState {
+    fn path(app_dir: &Path) -> PathBuf {
+        app_dir.join(REPORT_DIR).join(STATE_FILE)
+    }
+
+    pub fn load(app_dir: &Path) -> ServicesState {
+        fs::read_to_string(Self::path(app_dir))
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, app_dir: &Path) -> Result<()> {
+        let path = Self::path(app_dir);
+        let write = || {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let mut json = serde_json::to_string_pretty(self)?;
+            json.push('\n');
+            fs::write(&path, json)
+        };
+        write().map_err(|e| LumberstackError::io("Error saving service state", e))
+    }
+}
+
+pub struct Services;
+
+impl Services {
+    /// Starts the named services, recording the containers and volumes that didn't exist before
+    pub fn start(
+        runtime: ContainerRuntime,
+        app_name: &str,
+        names: &[String],
+        services: &[ServiceItem],
+        reporter: &dyn Reporter,
+    ) -> Result<()> {
+        let app_dir = Path::new(app_name);
+        let mut state = ServicesState::load(app_dir);
+        state.project_dir = fs::canonicalize(app_dir)
+            .unwrap_or(app_dir.to_path_buf())
+            .display()
+            .to_string();
+        state.runtime = runtime;
+
+        // whatever did get created is recorded even when a service fails, so clean finds it
+        let started = Self::start_each(runtime, app_name, names, services, &mut state, reporter);
+        let saved = state.save(app_dir);
+        started.and(saved)
+    }
+
+    fn start_each(
+        runtime: ContainerRuntime,
+        app_name: &str,
+        names: &[String],
+        services: &[ServiceItem],
+        state: &mut ServicesState,
+        reporter: &dyn Reporter,
+    ) -> Result<()> {
+        for name in names {
+            let service = services.iter().find(|s| &s.name == name).ok_or_else(|| {
+                LumberstackError::manifest(name, "service is not defined in `services`")
+            })?;
+            let context = service.context.as_deref().unwrap_or(app_name);
+            let compose_file = service
+                .compose_file
+                .as_deref()
+                .unwrap_or(DEFAULT_COMPOSE_FILE);
+            let compose = |args: &[&'static str]| runtime.compose_args(compose_file, args);
+
+            let running = Self::query(
+                runtime,
+                context,
+                &[compose(&["ps", "-q", "--status", "running"]), vec![name]].concat(),
+            )?;
+            if !running.is_empty() {
+                debug!("service {} is already running", name);
+                continue;
+            }
+            Self::check_ports(service)?;
+
+            reporter.report(&Event::Feedback {
+                message: format!("Starting {}", name),
+            });
+            let ps = [compose(&["ps", "-a", "-q"]), vec![name]].concat();
+            let containers_before = Self::query(runtime, context, &ps)?;
+            let volumes_before = Self::query(runtime, ".", &["volume", "ls", "-q"])?;
+
+            let up = [compose(&["up", "-d"]), vec![name]].concat();
+            // a failed `up` can still have created some of them
+            let up = Commands::exec_raw(context, runtime.cli(), &up, true, None, reporter);
+
+            let containers = Self::query(runtime, context, &ps)?;
+            let mut volumes = vec![];
+            for container in containers.iter() {
+                for volume in Self::mounted_volumes(runtime, container)? {
+                    if !volumes_before.contains(&volume) && !volumes.contains(&volume) {
+                        volumes.push(volume);
+                    }
+                }
+            }
+            let containers: Vec<String> = containers
+                .into_iter()
+                .filter(|c| !containers_before.contains(c))
+                .collect();
+
+            let index = state
+                .services
+                .iter()
+                .position(|s| &s.name == name && s.context == context);
+            let started = match index {
+                Some(i) => &mut state.services[i],
+                None => {
+                    state.services.push(StartedService {
+                        name: name.to_owned(),
+                        context: context.to_string(),
+                        compose_file: compose_file.to_string(),
+                        ..Default::default()
+                    });
+                    state.services.last_mut().unwrap()
+                }
+            };
+            started.containers.extend(containers);
+            started.volumes.extend(volumes);
+            up?;
+        }
+        Ok(())
+    }
+
+    /// Stops services that asked not to be left running, they stay around for `clean`
+    pub fn stop(app_name: &str, services: &[ServiceItem], reporter: &dyn Reporter) {
+        let state = ServicesState::load(Path::new(app_name));
+        for started in state.services.iter() {
+            let keep_running = services
+                .iter()
+                .find(|s| s.name == started.name)
+                .is_none_or(|s| s.keep_running.unwrap_or(true));
+            if keep_running {
+                continue;
+            }
+
+            debug!("stopping service {}", started.name);
+            let runtime = state.runtime;
+            let args = [
+                runtime.compose_args(&started.compose_file, &["stop"]),
+                vec![&started.name],
+            ]
+            .concat();
+            if let Err(e) =
+                Commands::exec_raw(&started.context, runtime.cli(), &args, true, None, reporter)
+            {
+                warn!("Could not stop {} - {}", started.name, e);
+            }
+        }
+    }
+
+    /// Removes the containers and volumes recorded for the app at `app_dir`
+    pub fn clean(app_dir: &Path) -> Result<()> {
+        let state = ServicesState::load(app_dir);
+        if state.services.is_empty() {
+            debug!("no services recorded for {}", app_dir.display());
+            return Ok(());
+        }
+
+        let cli = state.runtime.cli();
+        for service in state.services.iter() {
+            info!("Removing service {}", service.name);
+            if !service.containers.is_empty() {
+                let args: Vec<&str> = ["rm", "-f"]
+                    .into_iter()
+                    .chain(service.containers.iter().map(|c| c.as_str()))
+                    .collect();
+                Commands::exec_raw(".", cli, &args, false, None, &SilentReporter)?;
+            }
+            if !service.volumes.is_empty() {
+                let args: Vec<&str> = ["volume", "rm", "-f"]
+                    .into_iter()
+                    .chain(service.volumes.iter().map(|v| v.as_str()))
+                    .collect();
+                Commands::exec_raw(".", cli, &args, false, None, &SilentReporter)?;
+            }
+        }
+
+        let path = ServicesState::path(app_dir);
+        fs::remove_file(&path)
+            .map_err(|e| LumberstackError::io(format!("Error removing {}", path.display()), e))
+    }
+
+    fn check_ports(service: &ServiceItem) -> Result<()> {
+        for port in service.ports.iter().flatten() {
+            if TcpListener::bind(("0.0.0.0", *port)).is_err() {
+                return Err(LumberstackError::Service {
+                    name: service.name.to_owned(),
+                    message: format!("port {} is already in use", port),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn mounted_volumes(runtime: ContainerRuntime, container: &str) -> Result<Vec<String>> {
+        let format = "{{range .Mounts}}{{if .Name}}{{.Name}} {{end}}{{end}}";
+        let output = Self::query(runtime, ".", &["inspect", "-f", format, container])?;
+        Ok(output
+            .iter()
+            .flat_map(|line| line.split_whitespace())
+            .map(String::from)
+            .collect())
+    }
+
+    /// Runs the container cli quietly, returning its output lines
+    fn query(runtime: ContainerRuntime, context: &str, args: &[&str]) -> Result<Vec<String>> {
+        let command = format!("{} {}", runtime.cli(), args.join(" "));
+        let output = Command::new(runtime.cli())
+            .args(args)
+            .current_dir(context)
+            .output()
+            .map_err(|e| LumberstackError::command(&command, e))?;
+
+        if !output.status.success() {
+            return Err(LumberstackError::command(
+                &command,
+                String::from_utf8_lossy(&output.stderr).trim(),
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| l.trim().to_string())
+            .collect())
+    }
+}
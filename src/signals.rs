@@ -0,0 +1,100 @@
+use crate::error::{LumberstackError, Result};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, OnceLock,
+};
+
+// the last SIGINT/SIGTERM received, 0 until then
+static RECEIVED: OnceLock<Arc<AtomicUsize>> = OnceLock::new();
+
+#[cfg(unix)]
+static TERMINAL: OnceLock<libc::termios> = OnceLock::new();
+
+pub struct Signals;
+
+impl Signals {
+    /// Catches SIGINT and SIGTERM instead of dying on them, so a running command can be
+    /// shut down and the terminal put back. Left to the binary, embedders keep their own handling.
+    pub fn install() -> Result<()> {
+        let received = RECEIVED.get_or_init(|| Arc::new(AtomicUsize::new(0)));
+        for signal in [SIGINT, SIGTERM] {
+            signal_hook::flag::register_usize(signal, received.clone(), signal as usize)
+                .map_err(|e| LumberstackError::io("Error installing signal handlers", e))?;
+        }
+        Terminal::save();
+        Ok(())
+    }
+
+    pub fn received() -> Option<i32> {
+        match RECEIVED.get().map(|r| r.load(Ordering::SeqCst)) {
+            Some(0) | None => None,
+            Some(signal) => Some(signal as i32),
+        }
+    }
+
+    /// Stops the build between commands and steps once a signal came in
+    pub fn check() -> Result<()> {
+        match Self::received() {
+            Some(signal) => Err(LumberstackError::Interrupted(signal)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn name(signal: i32) -> &'static str {
+        match signal {
+            SIGINT => "SIGINT",
+            SIGTERM => "SIGTERM",
+            _ => "signal",
+        }
+    }
+}
+
+/// Terminal settings from before anything ran, children like `yarn create` can leave it in raw mode
+pub struct Terminal;
+
+impl Terminal {
+    #[cfg(unix)]
+    fn save() {
+        let mut termios = std::mem::MaybeUninit::<libc::termios>::uninit();
+        // SAFETY: tcgetattr fills in termios when it returns 0
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) } == 0 {
+            let _ = TERMINAL.set(unsafe { termios.assume_init() });
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn save() {}
+
+    #[cfg(unix)]
+    pub fn restore() {
+        if let Some(termios) = TERMINAL.get() {
+            // SAFETY: termios came from tcgetattr on the same fd
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios) };
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn restore() {}
+
+    /// Makes `pgid` the terminal's foreground group, so a command in its own group can
+    /// still prompt and gets Ctrl-C. Only async-signal-safe calls, it also runs in the child.
+    #[cfg(unix)]
+    pub fn hand_to(pgid: libc::pid_t) -> bool {
+        // SAFETY: signal and tcsetpgrp on stdin, a background group changing the foreground
+        // gets SIGTTOU unless it's ignored while doing so
+        unsafe {
+            let previous = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+            let handed = libc::tcsetpgrp(libc::STDIN_FILENO, pgid) == 0;
+            libc::signal(libc::SIGTTOU, previous);
+            handed
+        }
+    }
+
+    /// Takes the terminal back once a command that was handed it is done
+    #[cfg(unix)]
+    pub fn take_back() {
+        // SAFETY: getpgrp can't fail
+        Self::hand_to(unsafe { libc::getpgrp() });
+    }
+}
@@ -21,5 +21,5 @@ pub fn create_spinner() -> ProgressBar {
         ]),
     );
     spinner.enable_steady_tick(Duration::from_millis(120));
-    return spinner;
+    spinner
 }
@@ -1,62 +1,216 @@
-use std::process::{exit, Command};
+use log::debug;
+use regex::Regex;
+use std::{collections::BTreeMap, io, path::Path, process::Command};
 
-use clap::Parser;
-use indicatif::ProgressBar;
-use log::error;
-
-use crate::cli_args::CliArgs;
+use crate::container::ContainerRuntime;
+use crate::error::{LumberstackError, Result};
+use crate::manifest::{BuildItem, Check, CheckItem, Manifest};
+use crate::package_manager::PackageManager;
+use crate::reporter::{Event, Reporter};
+use crate::requirements::Requirements;
 
 pub struct System;
 
 impl System {
-    pub fn check_prerequsites(spinner: &ProgressBar) {
-        let args = CliArgs::parse();
-        if !args.disable_checks {
-            spinner.set_prefix("🚀");
-            spinner.set_message("Checking system requirements");
-            Self::os_ok();
-            Self::check_app_installed("yarn");
-            Self::check_docker();
-            Self::check_app_installed("node");
-            Self::check_node_version();
+    /// Checks what the selected steps need, plus the manifest's own checks
+    pub fn check_prerequsites(
+        manifest: &Manifest,
+        items: &[&BuildItem],
+        reporter: &dyn Reporter,
+    ) -> Result<()> {
+        reporter.report(&Event::ChecksStarted);
+        Self::os_ok()?;
+
+        let tools = Self::required_tools(manifest, items);
+        for tool in tools.iter() {
+            if tool == manifest.container_runtime.cli() {
+                Self::check_container_runtime(manifest.container_runtime)?;
+            } else if tool == manifest.package_manager.cli() {
+                Self::check_package_manager(manifest.package_manager)?;
+            } else {
+                Self::check_app_installed(tool)?;
+            }
+        }
+        Self::check_requirements(manifest, &tools, reporter)?;
+
+        let step_checks = items.iter().flat_map(|item| item.checks.iter().flatten());
+        for check in manifest.json.checks.iter().flatten().chain(step_checks) {
+            Self::check_custom(check)?;
         }
+        Ok(())
     }
 
-    fn os_ok() {
-        if cfg!(windows) {
-            error!("❌ Windows is not supported at this time");
-            exit(exitcode::SOFTWARE);
+    /// Every tool the steps list in `requires`, and the container runtime for steps
+    /// that start services
+    pub(crate) fn required_tools(manifest: &Manifest, items: &[&BuildItem]) -> Vec<String> {
+        let mut tools: Vec<String> = vec![];
+        for item in items.iter() {
+            let runtime = item
+                .services
+                .as_ref()
+                .map(|_| manifest.container_runtime.cli().to_string());
+            for tool in item.requires.iter().flatten().cloned().chain(runtime) {
+                if !tools.contains(&tool) {
+                    tools.push(tool);
+                }
+            }
+        }
+        tools
+    }
+
+    /// Versions from the manifest's `requirements`, for the tools that are needed
+    fn check_requirements(
+        manifest: &Manifest,
+        tools: &[String],
+        reporter: &dyn Reporter,
+    ) -> Result<()> {
+        let requirements: BTreeMap<String, String> = manifest
+            .json
+            .requirements
+            .iter()
+            .flatten()
+            .filter(|(tool, _)| tools.contains(tool))
+            .map(|(tool, range)| (tool.to_owned(), range.to_owned()))
+            .collect();
+        if requirements.is_empty() {
+            return Ok(());
+        }
+
+        let rows = Requirements::check(&requirements);
+        reporter.report(&Event::RequirementsChecked { rows: rows.clone() });
+        Requirements::verify(&rows)
+    }
+
+    pub(crate) fn check_custom(item: &CheckItem) -> Result<()> {
+        let name = item.label();
+        debug!("checking {}", name);
+        let failed =
+            |message: String| LumberstackError::SystemCheck(format!("{} - {}", name, message));
+
+        match &item.check {
+            Check::Command(command) => {
+                let mut args = command.split_whitespace();
+                let program = args.next().unwrap_or_default();
+                let output = Command::new(program)
+                    .args(args)
+                    .output()
+                    .map_err(|e| failed(e.to_string()))?;
+                let text = format!(
+                    "{}{}",
+                    String::from_utf8_lossy(&output.stdout),
+                    String::from_utf8_lossy(&output.stderr)
+                );
+                match &item.expect {
+                    Some(expect) => {
+                        let re = Regex::new(expect).map_err(|e| {
+                            LumberstackError::manifest(&name, format!("expect - {}", e))
+                        })?;
+                        if !re.is_match(&text) {
+                            return Err(failed(format!("output doesn't match `{}`", expect)));
+                        }
+                    }
+                    None if !output.status.success() => {
+                        return Err(failed(format!("exited with {}", output.status)))
+                    }
+                    None => {}
+                }
+            }
+            Check::Env(var) => {
+                if std::env::var_os(var).is_none_or(|v| v.is_empty()) {
+                    return Err(failed("not set".to_string()));
+                }
+            }
+            Check::File(path) => {
+                if !Path::new(path).exists() {
+                    return Err(failed("not found".to_string()));
+                }
+            }
+            Check::FreeDiskMb(required) => {
+                let path = Path::new(item.path.as_deref().unwrap_or("."));
+                let free = Self::free_disk_mb(path).map_err(|e| failed(e.to_string()))?;
+                if free < *required {
+                    return Err(failed(format!(
+                        "{}MB free in {} but {}MB required",
+                        free,
+                        path.display(),
+                        required
+                    )));
+                }
+            }
         }
+        Ok(())
     }
 
-    fn check_node_version() {
-        let output = Self::check_app_installed("node");
-        if !output.contains("v14") {
-            error!("❌ node v14 required but found: {}", output);
-            exit(exitcode::SOFTWARE);
+    /// Space available to us on the disk holding `path`, or its nearest existing parent
+    #[cfg(unix)]
+    pub(crate) fn free_disk_mb(path: &Path) -> io::Result<u64> {
+        use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+        let existing = path
+            .ancestors()
+            .find(|p| p.exists())
+            .unwrap_or(Path::new("."));
+        let c_path = CString::new(existing.as_os_str().as_bytes())?;
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+            return Err(io::Error::last_os_error());
         }
+        Ok(stat.f_bavail as u64 * stat.f_frsize as u64 / 1024 / 1024)
     }
 
-    fn check_app_installed(bin_name: &str) -> String {
-        let output = Command::new(bin_name).arg("-v").output();
-        if output.is_err() {
-            error!("❌ {} not found but required", bin_name);
-            exit(exitcode::SOFTWARE);
+    #[cfg(not(unix))]
+    pub(crate) fn free_disk_mb(_path: &Path) -> io::Result<u64> {
+        Ok(u64::MAX)
+    }
+
+    pub(crate) fn os_ok() -> Result<()> {
+        if cfg!(windows) {
+            return Err(LumberstackError::SystemCheck(
+                "Windows is not supported at this time".to_string(),
+            ));
         }
+        Ok(())
+    }
+
+    pub(crate) fn check_package_manager(package_manager: PackageManager) -> Result<()> {
+        let output = Self::check_app_installed(package_manager.cli())?;
+        if package_manager == PackageManager::YarnBerry && output.starts_with("1.") {
+            return Err(LumberstackError::SystemCheck(format!(
+                "yarn-berry selected but found yarn {} (try `corepack enable`)",
+                output.trim()
+            )));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_app_installed(bin_name: &str) -> Result<String> {
+        let output = Command::new(bin_name).arg("-v").output().map_err(|_| {
+            LumberstackError::SystemCheck(format!("{} not found but required", bin_name))
+        })?;
 
-        return String::from_utf8(output.unwrap().stdout).unwrap();
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
     }
 
-    fn check_docker() {
-        Self::check_app_installed("docker");
-        let output = Command::new("docker").arg("ps").output().unwrap();
+    pub(crate) fn check_container_runtime(runtime: ContainerRuntime) -> Result<()> {
+        let cli = runtime.cli();
+        Self::check_app_installed(cli)?;
+        let output = Command::new(cli)
+            .arg("ps")
+            .output()
+            .map_err(|e| LumberstackError::SystemCheck(format!("Error running {} - {}", cli, e)))?;
 
-        // let out = String::from_utf8(output.stdout).unwrap();
-        let err = String::from_utf8(output.stderr).unwrap();
+        let err = String::from_utf8_lossy(&output.stderr);
 
-        if err.contains("Error response") || err.contains("Cannot connect") {
-            error!("❌ Docker not running");
-            exit(exitcode::SOFTWARE);
+        if !output.status.success()
+            || err.contains("Error response")
+            || err.contains("Cannot connect")
+        {
+            return Err(LumberstackError::SystemCheck(format!(
+                "{} not running - {}",
+                cli,
+                err.trim()
+            )));
         }
+        Ok(())
     }
 }
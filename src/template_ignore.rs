@@ -0,0 +1,98 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use log::{debug, warn};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+static IGNORE_FILE: &str = ".lumberstackignore";
+
+// Always skipped, template authors can re-include with `!pattern` in an ignore file
+static DEFAULT_IGNORES: &[&str] = &[
+    ".lumberstackignore",
+    ".git/",
+    ".DS_Store",
+    "Thumbs.db",
+    "node_modules/",
+    "*.swp",
+    "*.swo",
+    "*~",
+];
+
+/// Gitignore style `.lumberstackignore` files, honored at any level of a template tree
+pub struct TemplateIgnore {
+    root: PathBuf,
+    defaults: Gitignore,
+    dirs: HashMap<PathBuf, Option<Gitignore>>,
+}
+
+impl TemplateIgnore {
+    pub fn new(root: &Path) -> TemplateIgnore {
+        let mut builder = GitignoreBuilder::new(root);
+        for pattern in DEFAULT_IGNORES {
+            if let Err(e) = builder.add_line(None, pattern) {
+                warn!("Invalid default ignore {} - {}", pattern, e);
+            }
+        }
+
+        TemplateIgnore {
+            root: root.to_path_buf(),
+            defaults: builder.build().unwrap_or_else(|_| Gitignore::empty()),
+            dirs: HashMap::new(),
+        }
+    }
+
+    /// Checks ignore files from the entry's own directory up to the root, closest one wins
+    pub fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        let mut dir = path.parent();
+        while let Some(current) = dir {
+            let matched = match self.matcher(current) {
+                Some(gitignore) => gitignore.matched(path, is_dir),
+                None => ignore::Match::None,
+            };
+
+            if matched.is_ignore() {
+                debug!("ignored: {}", path.display());
+                return true;
+            }
+            if matched.is_whitelist() {
+                return false;
+            }
+
+            if current == self.root {
+                break;
+            }
+            dir = current.parent();
+        }
+
+        let ignored = self.defaults.matched(path, is_dir).is_ignore();
+        if ignored {
+            debug!("ignored by default: {}", path.display());
+        }
+        ignored
+    }
+
+    fn matcher(&mut self, dir: &Path) -> Option<&Gitignore> {
+        self.dirs
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                let file = dir.join(IGNORE_FILE);
+                if !file.is_file() {
+                    return None;
+                }
+
+                let mut builder = GitignoreBuilder::new(dir);
+                if let Some(e) = builder.add(&file) {
+                    warn!("Error reading {} - {}", file.display(), e);
+                }
+                match builder.build() {
+                    Ok(gitignore) => Some(gitignore),
+                    Err(e) => {
+                        warn!("Error parsing {} - {}", file.display(), e);
+                        None
+                    }
+                }
+            })
+            .as_ref()
+    }
+}
@@ -1,143 +1,511 @@
-use crate::manifest::TemplateItem;
-use image;
-use indicatif::ProgressBar;
-use log::{debug, error, warn};
+use crate::conflicts::{Conflicts, FileOutcome, TemplateSummary};
+use crate::error::{LumberstackError, Result};
+use crate::manifest::{ArrayStrategy, ConflictPolicy, TemplateItem};
+use crate::merge::Merge;
+use crate::reporter::{Event, Reporter};
+use crate::template_ignore::TemplateIgnore;
+use crate::transaction::Transaction;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::{debug, warn};
+use regex::Regex;
+use sha2::{Digest, Sha256};
 use std::error::Error;
+
+type CopyResult<T> = std::result::Result<T, Box<dyn Error>>;
 use std::{
-    fs,
+    collections::BTreeMap,
+    fs::{self, Permissions},
     io::ErrorKind,
-    path::{Path, PathBuf},
-    process::exit,
+    path::{Component, Path, PathBuf},
+    time::Instant,
 };
 use walkdir::WalkDir;
 
+// Same heuristic git uses: a NUL byte in the first 8000 bytes means binary
+const BINARY_SNIFF_LEN: usize = 8000;
+
+// `name__if_auth__` is only copied (as `name`) when the `auth` feature is on, `__unless_` inverts it
+const CONDITION_PATTERN: &str = r"__(if|unless)_([\w-]+?)__$";
+
+struct CopyContext<'a> {
+    vars: BTreeMap<String, String>,
+    features: Vec<String>,
+    condition: &'a Regex,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    raw_globs: GlobSet,
+    on_conflict: ConflictPolicy,
+    array_strategy: ArrayStrategy,
+    reporter: &'a dyn Reporter,
+}
+
 pub struct Templates;
 
 impl Templates {
-    pub fn process(app_name: &String, template_items: Vec<TemplateItem>, spinner: &ProgressBar) {
-        spinner.set_prefix("📄");
+    pub fn process(
+        app_name: &str,
+        template_items: Vec<TemplateItem>,
+        on_conflict: ConflictPolicy,
+        reporter: &dyn Reporter,
+    ) -> Result<()> {
+        let condition = Regex::new(CONDITION_PATTERN).unwrap();
+
+        // validate every item up front so nothing is written for a step that can't finish
+        let mut contexts = vec![];
         for template_item in template_items.iter() {
-            let feedback = template_item.feedback.to_owned();
+            let source = PathBuf::from(&template_item.source);
 
-            if let Some(feedback) = feedback {
-                spinner.set_message(feedback);
+            if !source.exists() {
+                return Err(LumberstackError::template(
+                    &template_item.source,
+                    "File or Folder does not exist",
+                ));
             }
 
-            let source = PathBuf::from(&template_item.source);
-            let dest = PathBuf::from(&template_item.dest);
+            let globs = |patterns: Option<&Vec<String>>, kind: &str| {
+                Self::build_globs(patterns.into_iter().flatten(), kind)
+                    .map_err(|e| LumberstackError::manifest(&template_item.source, e))
+            };
 
-            let source_exists = Path::new(&source).exists();
+            let mut vars = template_item.replace_map.clone().unwrap_or_default();
+            vars.insert("app_name".to_string(), app_name.to_string());
+
+            let ctx = CopyContext {
+                vars,
+                features: template_item.features.clone().unwrap_or_default(),
+                condition: &condition,
+                include: match &template_item.include {
+                    Some(patterns) => Some(globs(Some(patterns), "include")?),
+                    None => None,
+                },
+                exclude: globs(template_item.exclude.as_ref(), "exclude")?,
+                raw_globs: globs(template_item.raw_globs.as_ref(), "raw")?,
+                on_conflict: template_item.on_conflict.unwrap_or(on_conflict),
+                array_strategy: template_item.array_strategy.unwrap_or_default(),
+                reporter,
+            };
+            contexts.push((template_item, source, ctx));
+        }
 
-            if !source_exists {
-                error!("File or Folder {} does not exist", source.to_string_lossy());
-                exit(exitcode::OSFILE);
+        let mut transaction = Transaction::begin()
+            .map_err(|e| LumberstackError::io("Error creating template staging dir", e))?;
+        let mut summary = TemplateSummary::default();
+
+        for (template_item, source, ctx) in contexts.iter() {
+            reporter.report(&Event::TemplateStarted {
+                source: template_item.source.to_owned(),
+                dest: template_item.dest.to_owned(),
+            });
+            if let Some(message) = template_item.feedback.to_owned() {
+                reporter.report(&Event::Feedback { message });
             }
 
-            let result = Self::copy_all_templates(&app_name, &source, &dest);
+            let started = Instant::now();
+            let dest = PathBuf::from(&template_item.dest);
+            let follow_symlinks = template_item.follow_symlinks.unwrap_or(false);
+            let result = Self::copy_all_templates(
+                ctx,
+                source,
+                &dest,
+                follow_symlinks,
+                &mut transaction,
+                &mut summary,
+            );
 
             if let Err(e) = result {
-                error!("Error copying templates: {}", e);
-                exit(exitcode::OSFILE);
+                let message = match transaction.rollback() {
+                    Ok(()) => format!("{} (changes rolled back)", e),
+                    Err(rollback) => format!("{} (rolling back failed - {})", e, rollback),
+                };
+                return Err(LumberstackError::template(&template_item.source, message));
             }
+
+            reporter.report(&Event::TemplateFinished {
+                source: template_item.source.to_owned(),
+                dest: template_item.dest.to_owned(),
+                duration_ms: started.elapsed().as_millis() as u64,
+            });
+        }
+
+        transaction.commit();
+
+        // only report files once they are in place for good
+        for (path, outcome) in summary.files.iter() {
+            reporter.report(&Event::file_written(path, *outcome, Self::hash(path)));
+        }
+        Ok(())
+    }
+
+    /// sha256 of a regular file on disk, symlinks are left out
+    fn hash(path: &Path) -> Option<String> {
+        match fs::symlink_metadata(path) {
+            Ok(meta) if meta.is_file() => fs::read(path)
+                .ok()
+                .map(|bytes| format!("{:x}", Sha256::digest(bytes))),
+            _ => None,
         }
     }
 
+    fn build_globs<'a>(
+        patterns: impl IntoIterator<Item = &'a String>,
+        kind: &str,
+    ) -> std::result::Result<GlobSet, String> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            let glob = Glob::new(pattern)
+                .map_err(|e| format!("Invalid {} glob [{}] - {}", kind, pattern, e))?;
+            builder.add(glob);
+        }
+        builder
+            .build()
+            .map_err(|e| format!("Error building {} globs - {}", kind, e))
+    }
+
     fn copy_all_templates(
-        app_name: &String,
-        in_dir: &PathBuf,
-        out_dir: &PathBuf,
-    ) -> Result<(), Box<dyn Error>> {
-        for entry in WalkDir::new(&in_dir) {
+        ctx: &CopyContext,
+        in_dir: &Path,
+        out_dir: &Path,
+        follow_symlinks: bool,
+        tx: &mut Transaction,
+        summary: &mut TemplateSummary,
+    ) -> CopyResult<()> {
+        let mut ignores = TemplateIgnore::new(in_dir);
+        let walker = WalkDir::new(in_dir)
+            .follow_links(follow_symlinks)
+            .into_iter()
+            // prune ignored, excluded and switched off directories instead of walking them
+            .filter_entry(|entry| {
+                // an explicitly named source is always copied
+                if entry.depth() == 0 {
+                    return true;
+                }
+                if ignores.is_ignored(entry.path(), entry.file_type().is_dir()) {
+                    return false;
+                }
+                match entry.path().strip_prefix(in_dir) {
+                    Ok(relative) => Self::is_wanted(ctx, relative),
+                    Err(_) => true,
+                }
+            });
+
+        for entry in walker {
             let entry = entry?;
 
             let from = entry.path();
-            let to = out_dir.join(from.strip_prefix(&in_dir)?);
+            let relative = from.strip_prefix(in_dir)?;
+            // a single file source has an empty relative path, match on its name instead
+            let glob_path = if relative.as_os_str().is_empty() {
+                Path::new(entry.file_name())
+            } else {
+                relative
+            };
+            // joining an empty path would add a trailing slash for single file sources
+            let to = if relative.as_os_str().is_empty() {
+                out_dir.to_path_buf()
+            } else {
+                out_dir.join(Self::render_path(ctx, relative))
+            };
 
             let file_type = entry.file_type();
 
-            // create directories
+            // directories are created as files land in them, so filtered trees leave no empty dirs
             if file_type.is_dir() {
-                Self::create_dir(&to);
-            } else if file_type.is_file() {
-                let from = from.to_path_buf();
-                Self::copy_template(&app_name, &from, to)?;
+                continue;
+            }
+
+            if let Some(include) = &ctx.include {
+                if !include.is_match(glob_path) {
+                    debug!("not included: {}", from.display());
+                    continue;
+                }
+            }
+
+            if let Some(parent) = to.parent() {
+                debug!("creating dir: {}", parent.display());
+                tx.create_dir_all(parent)?;
+            }
+
+            if file_type.is_file() {
+                let is_raw = ctx.raw_globs.is_match(glob_path);
+                Self::copy_template(ctx, from, &to, is_raw, tx, summary)?;
+            } else if file_type.is_symlink() {
+                Self::copy_symlink(ctx, in_dir, from, &to, tx, summary)?;
             } else {
-                warn!("copy: ignored symlink {}", from.display());
+                warn!("copy: ignored special file {}", from.display());
             }
         }
         Ok(())
     }
 
-    fn copy_template(
-        app_name: &String,
-        from: &PathBuf,
-        to: PathBuf,
-    ) -> Result<(), Box<dyn Error>> {
-        if Self::is_image_file(from) {
-            Self::write_image(from, &to);
-            return Ok(());
+    fn is_wanted(ctx: &CopyContext, relative: &Path) -> bool {
+        if ctx.exclude.is_match(relative) {
+            debug!("excluded: {}", relative.display());
+            return false;
         }
 
-        debug!("copying template: {} to {}", from.display(), to.display());
+        let file_name = relative.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        match ctx.condition.captures(file_name) {
+            Some(caps) => {
+                let enabled = ctx.features.iter().any(|f| f == &caps[2]);
+                let wanted = (&caps[1] == "if") == enabled;
+                if !wanted {
+                    debug!(
+                        "feature {} is off, skipping {}",
+                        &caps[2],
+                        relative.display()
+                    );
+                }
+                wanted
+            }
+            None => true,
+        }
+    }
+
+    /// Interpolates variables in every path segment and drops `__if_<feature>__` suffixes
+    fn render_path(ctx: &CopyContext, relative: &Path) -> PathBuf {
+        relative
+            .components()
+            .map(|component| {
+                let segment = component.as_os_str().to_string_lossy();
+                let segment = ctx.condition.replace(&segment, "");
+                Self::interpolate(&segment, &ctx.vars)
+            })
+            .collect()
+    }
+
+    fn interpolate(text: &str, vars: &BTreeMap<String, String>) -> String {
+        let mut replaced = text.to_string();
+        for (key, value) in vars {
+            replaced = replaced.replace(&format!("{{{{{}}}}}", key), value);
+        }
+        replaced
+    }
+
+    fn copy_template(
+        ctx: &CopyContext,
+        from: &Path,
+        to: &Path,
+        is_raw: bool,
+        tx: &mut Transaction,
+        summary: &mut TemplateSummary,
+    ) -> CopyResult<()> {
+        let bytes = fs::read(from)?;
+        let is_text = !(is_raw || Self::is_binary(&bytes));
+
+        let output = if is_text {
+            debug!("copying template: {} to {}", from.display(), to.display());
+            let file_str = String::from_utf8(bytes)?;
+            Self::interpolate(&file_str, &ctx.vars).into_bytes()
+        } else {
+            debug!("copying raw file: {} to {}", from.display(), to.display());
+            bytes
+        };
+
+        // keep mode bits so scripts and hooks stay executable
+        let permissions = fs::metadata(from)?.permissions();
+        let outcome = Self::write_output(ctx, to, output, permissions, is_text, tx)?;
 
-        let to = to.clone();
-        let file_str = fs::read_to_string(from);
+        summary.record(to, outcome);
+        Ok(())
+    }
+
+    fn write_output(
+        ctx: &CopyContext,
+        to: &Path,
+        output: Vec<u8>,
+        permissions: Permissions,
+        is_text: bool,
+        tx: &mut Transaction,
+    ) -> CopyResult<FileOutcome> {
+        let existing = match fs::read(to) {
+            Ok(existing) => existing,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                tx.write(to, &output, permissions)?;
+                return Ok(FileOutcome::Created);
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if existing == output {
+            tx.set_permissions(to, permissions)?;
+            return Ok(FileOutcome::Unchanged);
+        }
+
+        let policy = match ctx.on_conflict {
+            ConflictPolicy::Prompt => {
+                Conflicts::prompt(ctx.reporter, to, &existing, &output, is_text)?
+            }
+            policy => policy,
+        };
 
-        match &file_str {
-            Ok(str) => {
-                Self::write_file(&to, str, app_name);
-                Ok(())
+        match policy {
+            ConflictPolicy::Overwrite | ConflictPolicy::Prompt => {
+                tx.write(to, &output, permissions)?;
+                Ok(FileOutcome::Overwritten)
+            }
+            ConflictPolicy::Skip => {
+                debug!("skipping existing file {}", to.display());
+                Ok(FileOutcome::Skipped)
+            }
+            ConflictPolicy::Error => Err(format!("{} already exists", to.display()).into()),
+            ConflictPolicy::Backup => {
+                let backup = Self::backup_path(to);
+                debug!("backing up {} to {}", to.display(), backup.display());
+                tx.write(&backup, &existing, fs::metadata(to)?.permissions())?;
+                tx.write(to, &output, permissions)?;
+                Ok(FileOutcome::BackedUp)
             }
-            Err(_) => {
-                error!("Error interpolating file [{:?}]. Skipping...", &file_str);
-                Ok(())
+            ConflictPolicy::Merge => {
+                match Merge::merge(to, &existing, &output, ctx.array_strategy)? {
+                    Some(merged) if merged == existing => Ok(FileOutcome::Unchanged),
+                    Some(merged) => {
+                        tx.write(to, &merged, permissions)?;
+                        Ok(FileOutcome::Merged)
+                    }
+                    None => {
+                        warn!("Can't merge {}, overwriting instead", to.display());
+                        tx.write(to, &output, permissions)?;
+                        Ok(FileOutcome::Overwritten)
+                    }
+                }
             }
         }
     }
 
-    fn create_dir(to: &PathBuf) {
-        debug!("creating dir: {}", to.to_string_lossy());
-        if let Err(e) = fs::create_dir(to) {
-            match e.kind() {
-                ErrorKind::AlreadyExists => {
-                    debug!("already exists!");
+    /// `<file>.orig`, or `<file>.orig.1`, `.orig.2`... so re-runs never replace the first original
+    fn backup_path(to: &Path) -> PathBuf {
+        let mut backup = to.as_os_str().to_owned();
+        backup.push(".orig");
+        let first = PathBuf::from(&backup);
+        if fs::symlink_metadata(&first).is_err() {
+            return first;
+        }
+        (1..)
+            .map(|n| {
+                let mut numbered = backup.clone();
+                numbered.push(format!(".{}", n));
+                PathBuf::from(numbered)
+            })
+            .find(|path| fs::symlink_metadata(path).is_err())
+            .unwrap_or(first)
+    }
+
+    #[cfg(unix)]
+    fn copy_symlink(
+        ctx: &CopyContext,
+        in_dir: &Path,
+        from: &Path,
+        to: &Path,
+        tx: &mut Transaction,
+        summary: &mut TemplateSummary,
+    ) -> CopyResult<()> {
+        let target = fs::read_link(from)?;
+
+        if target.is_absolute() {
+            return Err(format!(
+                "absolute symlink {} -> {} is not allowed in templates",
+                from.display(),
+                target.display()
+            )
+            .into());
+        }
+
+        // resolve the target inside the template tree, then point at the same spot in dest
+        let link_dir = from.parent().unwrap_or(in_dir).strip_prefix(in_dir)?;
+        let resolved = Self::normalize_within(&link_dir.join(&target)).ok_or_else(|| {
+            format!(
+                "symlink {} -> {} points outside the template source",
+                from.display(),
+                target.display()
+            )
+        })?;
+        let link_dir = Self::normalize_within(link_dir).unwrap_or_default();
+        let rewritten = Self::relative_to(
+            &Self::render_path(ctx, &link_dir),
+            &Self::render_path(ctx, &resolved),
+        );
+
+        let mut outcome = FileOutcome::Created;
+        if let Ok(meta) = fs::symlink_metadata(to) {
+            if meta.is_dir() {
+                return Err(
+                    format!("cannot replace directory {} with a symlink", to.display()).into(),
+                );
+            }
+            if fs::read_link(to).ok().as_ref() == Some(&rewritten) {
+                summary.record(to, FileOutcome::Unchanged);
+                return Ok(());
+            }
+            // links have no content to merge or diff, so only skip and error apply
+            match ctx.on_conflict {
+                ConflictPolicy::Skip => {
+                    summary.record(to, FileOutcome::Skipped);
+                    return Ok(());
                 }
-                _ => {
-                    error!("Error creating destination dir! {}", e);
-                    exit(1);
+                ConflictPolicy::Error => {
+                    return Err(format!("{} already exists", to.display()).into());
                 }
+                _ => {}
             }
+            outcome = FileOutcome::Overwritten;
         }
+
+        debug!("linking {} -> {}", to.display(), rewritten.display());
+        tx.symlink(&rewritten, to)?;
+        summary.record(to, outcome);
+        Ok(())
     }
 
-    fn write_file(to: &PathBuf, str: &String, app_name: &String) {
-        let replaced = str.replace("{{app_name}}", &app_name);
-        if let Err(e) = fs::write(to, replaced) {
-            error!("error writing {} - {}", to.display(), e);
-        }
+    #[cfg(not(unix))]
+    fn copy_symlink(
+        _ctx: &CopyContext,
+        _in_dir: &Path,
+        from: &Path,
+        _to: &Path,
+        _tx: &mut Transaction,
+        _summary: &mut TemplateSummary,
+    ) -> CopyResult<()> {
+        warn!("copy: ignored symlink {}", from.display());
+        Ok(())
     }
 
-    fn write_image(from: &PathBuf, to: &PathBuf) {
-        debug!("writing image {} to {}", from.display(), to.display());
-        match image::open(from) {
-            Ok(img) => {
-                if let Err(e) = img.save(to) {
-                    error!("Error writing image: {} - {}", to.display(), e);
+    // Collapses `.` and `..` segments, returning None if the path climbs above its root
+    fn normalize_within(path: &Path) -> Option<PathBuf> {
+        let mut parts: Vec<&std::ffi::OsStr> = vec![];
+        for component in path.components() {
+            match component {
+                Component::Normal(part) => parts.push(part),
+                Component::ParentDir => {
+                    parts.pop()?;
                 }
+                Component::CurDir => {}
+                Component::RootDir | Component::Prefix(_) => return None,
             }
-            Err(e) => {
-                error!("Error opening image: {} - {}", from.display(), e);
-                exit(exitcode::OSFILE);
-            }
         }
+        Some(parts.iter().collect())
+    }
+
+    fn relative_to(base: &Path, target: &Path) -> PathBuf {
+        let base: Vec<Component> = base.components().collect();
+        let target: Vec<Component> = target.components().collect();
+        let common = base
+            .iter()
+            .zip(target.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        let mut relative = PathBuf::new();
+        for _ in common..base.len() {
+            relative.push("..");
+        }
+        for component in &target[common..] {
+            relative.push(component);
+        }
+        relative
     }
 
-    fn is_image_file(from: &PathBuf) -> bool {
-        let has_png = from.to_string_lossy().contains(".png");
-        let has_jpg = from.to_string_lossy().contains(".jpg");
-        let has_jpeg = from.to_string_lossy().contains(".jpeg");
-        let is_image = has_png | has_jpg | has_jpeg;
-        debug!("{} is an image: {}", from.display(), is_image);
-        return is_image;
+    fn is_binary(bytes: &[u8]) -> bool {
+        let sniff = &bytes[..bytes.len().min(BINARY_SNIFF_LEN)];
+        sniff.contains(&0) || std::str::from_utf8(bytes).is_err()
     }
 }
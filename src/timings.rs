@@ -0,0 +1,177 @@
+use crate::recorder::{StepRecord, StepStatus};
+use crate::report::REPORT_DIR;
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const HISTORY_FILE: &str = "timings.json";
+const HISTORY_RUNS: usize = 20;
+const TABLE_ROWS: usize = 15;
+
+// a timing regressed when it got a quarter slower and at least a second slower
+const REGRESSION_RATIO: f64 = 1.25;
+const REGRESSION_MIN_MS: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimingKind {
+    Step,
+    Command,
+    Template,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimingRow {
+    pub name: String,
+    pub kind: TimingKind,
+    pub duration_ms: u64,
+    /// The same timing in the last run that had it
+    pub previous_ms: Option<u64>,
+    pub regressed: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct HistoryRun {
+    generated_at: u64,
+    timings: BTreeMap<String, u64>,
+}
+
+/// Timings of previous runs, kept in `.lumberstack/timings.json` where lumberstack is run
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TimingHistory {
+    runs: Vec<HistoryRun>,
+}
+
+impl TimingHistory {
+    pub fn path() -> PathBuf {
+        Path::new(REPORT_DIR).join(HISTORY_FILE)
+    }
+
+    /// A missing or unreadable history just means there's nothing to compare with
+    pub fn load() -> TimingHistory {
+        fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    /// Every timing from this run, slowest first, compared with previous runs
+    pub fn rows(&self, steps: &[StepRecord]) -> Vec<TimingRow> {
+        let mut rows: Vec<TimingRow> = Self::timings(steps)
+            .into_iter()
+            .map(|(name, kind, duration_ms)| {
+                let previous_ms = self
+                    .runs
+                    .iter()
+                    .rev()
+                    .find_map(|run| run.timings.get(&name).copied());
+                let regressed = previous_ms.is_some_and(|previous| {
+                    duration_ms as f64 > previous as f64 * REGRESSION_RATIO
+                        && duration_ms.saturating_sub(previous) >= REGRESSION_MIN_MS
+                });
+                TimingRow {
+                    name,
+                    kind,
+                    duration_ms,
+                    previous_ms,
+                    regressed,
+                }
+            })
+            .collect();
+
+        rows.sort_by_key(|row| Reverse(row.duration_ms));
+        rows
+    }
+
+    /// Adds this run and writes the history back, dropping the oldest runs
+    pub fn save(mut self, steps: &[StepRecord]) -> io::Result<()> {
+        let timings: BTreeMap<String, u64> = Self::timings(steps)
+            .into_iter()
+            .map(|(name, _, duration_ms)| (name, duration_ms))
+            .collect();
+        if timings.is_empty() {
+            return Ok(());
+        }
+
+        self.runs.push(HistoryRun {
+            generated_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            timings,
+        });
+        let overflow = self.runs.len().saturating_sub(HISTORY_RUNS);
+        self.runs.drain(..overflow);
+
+        let path = Self::path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut json = serde_json::to_string_pretty(&self)?;
+        json.push('\n');
+        fs::write(path, json)
+    }
+
+    /// Only finished steps count, a failed step's time says nothing about the next run
+    fn timings(steps: &[StepRecord]) -> Vec<(String, TimingKind, u64)> {
+        let mut timings = vec![];
+        for step in steps.iter().filter(|s| s.status == StepStatus::Finished) {
+            if let Some(duration_ms) = step.duration_ms {
+                timings.push((step.tag.to_owned(), TimingKind::Step, duration_ms));
+            }
+            for command in step.commands.iter() {
+                let name = format!("{}: {}", step.tag, command.command);
+                timings.push((name, TimingKind::Command, command.duration_ms));
+            }
+            for template in step.templates.iter() {
+                let name = format!("{}: {} -> {}", step.tag, template.source, template.dest);
+                timings.push((name, TimingKind::Template, template.duration_ms));
+            }
+        }
+        timings
+    }
+}
+
+pub struct Timings;
+
+impl Timings {
+    /// The slowest rows as a table, regressions marked with 🐢
+    pub fn lines(rows: &[TimingRow]) -> Vec<String> {
+        if rows.is_empty() {
+            return vec![];
+        }
+
+        let mut lines = vec!["⏱️  Timings:".to_string()];
+        for row in rows.iter().take(TABLE_ROWS) {
+            let previous = match row.previous_ms {
+                Some(previous) => format!("(last run {})", Self::seconds(previous)),
+                None => String::new(),
+            };
+            let flag = if row.regressed { "🐢 " } else { "   " };
+            lines.push(
+                format!(
+                    "{}{:>8}  {:<9}{} {}",
+                    flag,
+                    Self::seconds(row.duration_ms),
+                    format!("{:?}", row.kind).to_lowercase(),
+                    row.name,
+                    previous
+                )
+                .trim_end()
+                .to_string(),
+            );
+        }
+        if rows.len() > TABLE_ROWS {
+            lines.push(format!("   ... and {} faster", rows.len() - TABLE_ROWS));
+        }
+        lines
+    }
+
+    fn seconds(ms: u64) -> String {
+        format!("{:.1}s", ms as f64 / 1000.0)
+    }
+}
@@ -0,0 +1,141 @@
+use log::{debug, warn};
+use std::{
+    collections::HashSet,
+    fs::{self, Permissions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use tempfile::TempDir;
+
+enum Original {
+    Missing,
+    File(PathBuf),
+    Symlink(PathBuf),
+}
+
+/// Journals every path a step touches so a failure can put the destination back the way it was.
+/// New content is staged in a temp file beside its destination and renamed into place.
+pub struct Transaction {
+    staging: TempDir,
+    originals: Vec<(PathBuf, Original)>,
+    tracked: HashSet<PathBuf>,
+    created_dirs: Vec<PathBuf>,
+}
+
+impl Transaction {
+    pub fn begin() -> io::Result<Transaction> {
+        Ok(Transaction {
+            staging: tempfile::Builder::new().prefix("lumberstack").tempdir()?,
+            originals: vec![],
+            tracked: HashSet::new(),
+            created_dirs: vec![],
+        })
+    }
+
+    /// Saves whatever is at `path` before the first change made to it
+    fn track(&mut self, path: &Path) -> io::Result<()> {
+        if !self.tracked.insert(path.to_path_buf()) {
+            return Ok(());
+        }
+
+        let original = match fs::symlink_metadata(path) {
+            Ok(meta) if meta.file_type().is_symlink() => Original::Symlink(fs::read_link(path)?),
+            Ok(_) => {
+                let backup = self.staging.path().join(self.originals.len().to_string());
+                fs::copy(path, &backup)?;
+                Original::File(backup)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Original::Missing,
+            Err(e) => return Err(e),
+        };
+
+        self.originals.push((path.to_path_buf(), original));
+        Ok(())
+    }
+
+    pub fn create_dir_all(&mut self, dir: &Path) -> io::Result<()> {
+        let mut missing: Vec<PathBuf> = dir
+            .ancestors()
+            .take_while(|d| !d.as_os_str().is_empty() && !d.exists())
+            .map(Path::to_path_buf)
+            .collect();
+
+        fs::create_dir_all(dir)?;
+
+        missing.reverse();
+        self.created_dirs.extend(missing);
+        Ok(())
+    }
+
+    pub fn write(
+        &mut self,
+        path: &Path,
+        contents: &[u8],
+        permissions: Permissions,
+    ) -> io::Result<()> {
+        self.track(path)?;
+
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mut staged = tempfile::Builder::new()
+            .prefix(".lumberstack")
+            .tempfile_in(parent)?;
+        staged.write_all(contents)?;
+        staged.as_file().set_permissions(permissions)?;
+        staged.persist(path).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    pub fn set_permissions(&mut self, path: &Path, permissions: Permissions) -> io::Result<()> {
+        self.track(path)?;
+        fs::set_permissions(path, permissions)
+    }
+
+    #[cfg(unix)]
+    pub fn symlink(&mut self, target: &Path, path: &Path) -> io::Result<()> {
+        self.track(path)?;
+        if fs::symlink_metadata(path).is_ok() {
+            fs::remove_file(path)?;
+        }
+        std::os::unix::fs::symlink(target, path)
+    }
+
+    pub fn commit(self) {
+        debug!("committed {} template paths", self.originals.len());
+    }
+
+    /// Restores every tracked path and removes directories this transaction created
+    pub fn rollback(self) -> io::Result<()> {
+        for (path, original) in self.originals.iter().rev() {
+            debug!("restoring {}", path.display());
+            if let Ok(meta) = fs::symlink_metadata(path) {
+                if meta.is_dir() {
+                    warn!("not restoring {}, it is now a directory", path.display());
+                    continue;
+                }
+                fs::remove_file(path)?;
+            }
+
+            match original {
+                Original::Missing => {}
+                Original::File(backup) => {
+                    fs::copy(backup, path)?;
+                }
+                #[cfg(unix)]
+                Original::Symlink(target) => std::os::unix::fs::symlink(target, path)?,
+                #[cfg(not(unix))]
+                Original::Symlink(_) => {}
+            }
+        }
+
+        for dir in self.created_dirs.iter().rev() {
+            // only empty dirs go, anything else was put there by someone else
+            if fs::remove_dir(dir).is_err() {
+                debug!("leaving {}", dir.display());
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+use crate::error::{LumberstackError, Result};
+use crate::manifest::{Probe, WaitItem};
+use crate::reporter::{Event, Reporter};
+use crate::signals::Signals;
+use log::debug;
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::Path,
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+const DEFAULT_INTERVAL: u64 = 1;
+const DEFAULT_TIMEOUT: u64 = 60;
+// a single tcp or http probe never takes longer than this
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+// how often a probe command is checked for exit
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub struct WaitFor;
+
+impl WaitFor {
+    pub fn process(items: Vec<WaitItem>, reporter: &dyn Reporter) -> Result<()> {
+        for item in items.iter() {
+            if let Some(message) = item.feedback.to_owned() {
+                reporter.report(&Event::Feedback { message });
+            }
+            Self::wait(item, reporter)?;
+        }
+        Ok(())
+    }
+
+    fn wait(item: &WaitItem, reporter: &dyn Reporter) -> Result<()> {
+        let probe = Self::describe(&item.probe);
+        let interval = Duration::from_secs(item.interval.unwrap_or(DEFAULT_INTERVAL));
+        let timeout = Duration::from_secs(item.timeout.unwrap_or(DEFAULT_TIMEOUT));
+        let context = item.context.as_deref().unwrap_or(".");
+
+        reporter.report(&Event::WaitStarted {
+            probe: probe.to_owned(),
+            timeout_ms: timeout.as_millis() as u64,
+        });
+
+        let started = Instant::now();
+        let mut last_error;
+        loop {
+            Signals::check()?;
+            // a command probe gets what's left of the timeout, but no less than other probes
+            let remaining = timeout.saturating_sub(started.elapsed()).max(PROBE_TIMEOUT);
+            match Self::probe(&item.probe, context, remaining) {
+                Ok(()) => break,
+                Err(e) => {
+                    debug!("{} not ready - {}", probe, e);
+                    last_error = e;
+                }
+            }
+            Signals::check()?;
+
+            let elapsed = started.elapsed();
+            if elapsed >= timeout {
+                return Err(LumberstackError::Wait {
+                    probe,
+                    message: format!("not ready after {}s ({})", timeout.as_secs(), last_error),
+                });
+            }
+            // one last probe right at the deadline
+            thread::sleep(interval.min(timeout - elapsed));
+        }
+
+        reporter.report(&Event::WaitFinished {
+            probe,
+            duration_ms: started.elapsed().as_millis() as u64,
+        });
+        Ok(())
+    }
+
+    pub(crate) fn describe(probe: &Probe) -> String {
+        match probe {
+            Probe::Tcp(address) => format!("tcp {}", address),
+            Probe::Http(url) => format!("http {}", url),
+            Probe::File(path) => format!("file {}", path),
+            Probe::Command(command) => format!("command [{}]", command),
+        }
+    }
+
+    /// One attempt, the error says why it isn't ready yet
+    fn probe(probe: &Probe, context: &str, timeout: Duration) -> std::result::Result<(), String> {
+        match probe {
+            Probe::Tcp(address) => Self::connect(address).map(|_| ()),
+            Probe::Http(url) => Self::http_ok(url),
+            Probe::File(path) => match Path::new(path).exists() {
+                true => Ok(()),
+                false => Err("does not exist".to_string()),
+            },
+            Probe::Command(command) => Self::run(command, context, timeout),
+        }
+    }
+
+    /// Runs a probe command, killing it and anything it started once `timeout` is up
+    fn run(command: &str, context: &str, timeout: Duration) -> std::result::Result<(), String> {
+        let cmd_vec: Vec<&str> = command.split(" ").collect();
+        let (program, args) = cmd_vec.split_at(1);
+        let mut cmd = Command::new(program[0]);
+        cmd.args(args)
+            .current_dir(context)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            cmd.process_group(0);
+        }
+
+        let mut child = cmd.spawn().map_err(|e| e.to_string())?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            match child.try_wait().map_err(|e| e.to_string())? {
+                Some(status) if status.success() => return Ok(()),
+                Some(status) => return Err(format!("exited with {}", status)),
+                None => {}
+            }
+            if Signals::received().is_some() {
+                Self::kill(&mut child);
+                return Err("interrupted".to_string());
+            }
+            if Instant::now() >= deadline {
+                Self::kill(&mut child);
+                return Err(format!("still running after {:.0}s", timeout.as_secs_f64()));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    #[cfg(unix)]
+    fn kill(child: &mut Child) {
+        // SAFETY: plain kill(2) on the probe's own process group
+        unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+        let _ = child.wait();
+    }
+
+    #[cfg(not(unix))]
+    fn kill(child: &mut Child) {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+
+    fn connect(address: &str) -> std::result::Result<TcpStream, String> {
+        let addrs = address.to_socket_addrs().map_err(|e| e.to_string())?;
+        let mut last_error = format!("{} did not resolve", address);
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, PROBE_TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = e.to_string(),
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Plain http only, enough for local dev servers
+    fn http_ok(url: &str) -> std::result::Result<(), String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or("only http:// urls can be probed")?;
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let address = match host.contains(':') {
+            true => host.to_string(),
+            false => format!("{}:80", host),
+        };
+
+        let mut stream = Self::connect(&address)?;
+        stream
+            .set_read_timeout(Some(PROBE_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        write!(
+            stream,
+            "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            path, host
+        )
+        .map_err(|e| e.to_string())?;
+
+        // only the status line matters
+        let mut head = [0; 64];
+        let n = stream.read(&mut head).map_err(|e| e.to_string())?;
+        let status_line = String::from_utf8_lossy(&head[..n]);
+        match status_line.split_whitespace().nth(1) {
+            Some("200") => Ok(()),
+            Some(code) => Err(format!("answered {}", code)),
+            None => Err("no http response".to_string()),
+        }
+    }
+}